@@ -1,5 +1,10 @@
-INSERT INTO users (username) VALUES('user');
-INSERT INTO users (username) VALUES('admin');
+-- All users have the password "password"
+INSERT INTO users (username, password_hash)
+    VALUES('user', '$argon2id$v=19$m=4096,t=3,p=1$dmFhc2ZpeHR1cmVzYWx0MQ$8yc4Bp7VhuWUJWUFqu3ATcYdUpbn4kgyB0Xdo6Lb7ek');
+INSERT INTO users (username, role, password_hash)
+    VALUES('admin', 'admin', '$argon2id$v=19$m=4096,t=3,p=1$dmFhc2ZpeHR1cmVzYWx0MQ$8yc4Bp7VhuWUJWUFqu3ATcYdUpbn4kgyB0Xdo6Lb7ek');
+INSERT INTO users (username, role, password_hash)
+    VALUES('observer', 'observer', '$argon2id$v=19$m=4096,t=3,p=1$dmFhc2ZpeHR1cmVzYWx0MQ$8yc4Bp7VhuWUJWUFqu3ATcYdUpbn4kgyB0Xdo6Lb7ek');
 INSERT INTO issues
     (id, title, description, state, max_voters, show_distribution)
     VALUES ('2a38614a-fd5b-4d4c-826d-809a656db2ea', 'coronvorus bad??', 'yes or yes', 'in_progress', 10, true);
@@ -0,0 +1,5 @@
+-- Add migration script here
+ALTER TABLE issues ADD COLUMN created_at timestamptz NOT NULL DEFAULT now();
+ALTER TABLE issues ADD COLUMN active boolean NOT NULL DEFAULT false;
+-- Only one issue can be active at a time
+CREATE UNIQUE INDEX issues_active_idx ON issues (active) WHERE active;
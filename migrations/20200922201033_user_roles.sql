@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE users ADD COLUMN role text NOT NULL DEFAULT 'voter'
+    CHECK (role IN ('admin', 'chair', 'voter', 'observer'));
@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE users ADD COLUMN password_hash text;
+ALTER TABLE users ADD COLUMN failed_login_attempts integer NOT NULL DEFAULT 0;
+ALTER TABLE users ADD COLUMN locked_until timestamptz;
@@ -0,0 +1,9 @@
+-- Add migration script here
+-- Usernames which only differ in case are renamed, except for the first one
+UPDATE users SET username = left(users.username, 31) || '-' || left(users.id::text, 8)
+FROM (
+    SELECT id, row_number() OVER (PARTITION BY lower(username) ORDER BY id) AS position
+    FROM users
+) duplicates
+WHERE duplicates.id = users.id AND duplicates.position > 1;
+CREATE UNIQUE INDEX users_username_idx ON users (lower(username));
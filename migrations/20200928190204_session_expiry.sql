@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE sessions ADD COLUMN created_at timestamptz NOT NULL DEFAULT now();
+ALTER TABLE sessions ADD COLUMN last_seen timestamptz NOT NULL DEFAULT now();
+ALTER TABLE sessions ADD COLUMN expires_at timestamptz NOT NULL DEFAULT now() + interval '7 days';
@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE issues ADD COLUMN allow_vote_change boolean NOT NULL DEFAULT false;
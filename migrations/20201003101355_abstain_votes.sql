@@ -0,0 +1,3 @@
+-- Add migration script here
+-- Votes without an alternative are abstentions
+ALTER TABLE votes ALTER COLUMN alternative_id DROP NOT NULL;
@@ -0,0 +1,12 @@
+-- Add migration script here
+ALTER TABLE issues ADD COLUMN secret boolean NOT NULL DEFAULT false;
+
+-- Ballots on secret issues are stored without the voter
+ALTER TABLE votes ALTER COLUMN user_id DROP NOT NULL;
+
+-- Records who has voted on secret issues without linking to the ballot
+CREATE TABLE IF NOT EXISTS ballot_receipts (
+    issue_id UUID references issues(id) NOT NULL,
+    user_id UUID references users(id) NOT NULL,
+    PRIMARY KEY (issue_id, user_id)
+);
@@ -0,0 +1,6 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS issue_results (
+    issue_id UUID PRIMARY KEY references issues(id),
+    result jsonb NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now()
+);
@@ -0,0 +1,15 @@
+-- Add migration script here
+ALTER TABLE issues ADD COLUMN majority text NOT NULL DEFAULT 'simple'
+    CHECK (majority IN ('simple', 'absolute', 'qualified'));
+-- Fraction of the counted votes the winner needs for a qualified majority
+ALTER TABLE issues ADD COLUMN qualified_numerator integer;
+ALTER TABLE issues ADD COLUMN qualified_denominator integer;
+ALTER TABLE issues ADD CONSTRAINT issues_qualified_fraction CHECK (
+    majority <> 'qualified'
+    OR (qualified_numerator > 0 AND qualified_denominator >= qualified_numerator)
+);
+-- Share of the eligible voters which has to take part
+ALTER TABLE issues ADD COLUMN quorum_percent integer NOT NULL DEFAULT 0
+    CHECK (quorum_percent BETWEEN 0 AND 100);
+-- Abstentions count as votes cast when deciding the majority
+ALTER TABLE issues ADD COLUMN count_abstentions boolean NOT NULL DEFAULT false;
@@ -0,0 +1,12 @@
+-- Add migration script here
+ALTER TABLE issues ADD COLUMN voting_method text NOT NULL DEFAULT 'plurality'
+    CHECK (voting_method IN ('plurality', 'ranked'));
+
+-- Alternatives of a ballot in order of preference, the vote itself keeps the first choice
+CREATE TABLE IF NOT EXISTS vote_choices (
+    vote_id UUID references votes(id) ON DELETE CASCADE NOT NULL,
+    position integer NOT NULL,
+    alternative_id UUID references alternatives(id) NOT NULL,
+    PRIMARY KEY (vote_id, position),
+    UNIQUE (vote_id, alternative_id)
+);
@@ -0,0 +1,11 @@
+-- Add migration script here
+ALTER TABLE issues DROP CONSTRAINT issues_voting_method_check;
+ALTER TABLE issues ADD CONSTRAINT issues_voting_method_check
+    CHECK (voting_method IN ('plurality', 'ranked', 'approval'));
+
+-- Number of alternatives an approval ballot has to select, no maximum means all of them
+ALTER TABLE issues ADD COLUMN min_choices integer NOT NULL DEFAULT 1;
+ALTER TABLE issues ADD COLUMN max_choices integer;
+ALTER TABLE issues ADD CONSTRAINT issues_choice_limits CHECK (
+    min_choices >= 1 AND (max_choices IS NULL OR max_choices >= min_choices)
+);
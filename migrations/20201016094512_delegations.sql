@@ -0,0 +1,18 @@
+-- Add migration script here
+-- A principal lets a proxy vote for them, on one issue or on all issues without one
+CREATE TABLE IF NOT EXISTS delegations (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    principal_id UUID references users(id) NOT NULL,
+    proxy_id UUID references users(id) NOT NULL,
+    issue_id UUID references issues(id),
+    created_at timestamptz NOT NULL DEFAULT now(),
+    CHECK (principal_id <> proxy_id)
+);
+-- Only one proxy per principal and issue
+CREATE UNIQUE INDEX delegations_principal_issue_idx ON delegations (
+    principal_id, coalesce(issue_id, '00000000-0000-0000-0000-000000000000')
+);
+
+-- Proxy who cast a vote for its owner
+ALTER TABLE votes ADD COLUMN cast_by UUID references users(id);
+ALTER TABLE ballot_receipts ADD COLUMN cast_by UUID references users(id);
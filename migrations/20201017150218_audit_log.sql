@@ -0,0 +1,10 @@
+-- Add migration script here
+-- Privileged actions kept for later review
+CREATE TABLE IF NOT EXISTS audit_log (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    actor_id UUID references users(id) NOT NULL,
+    action text NOT NULL,
+    user_id UUID references users(id),
+    issue_id UUID references issues(id),
+    created_at timestamptz NOT NULL DEFAULT now()
+);
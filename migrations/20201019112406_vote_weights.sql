@@ -0,0 +1,9 @@
+-- Add migration script here
+-- Shares of a user, votes count this many times
+ALTER TABLE users ADD COLUMN vote_weight integer NOT NULL DEFAULT 1 CHECK (vote_weight > 0);
+
+-- Weight of the voter when the vote was cast
+ALTER TABLE votes ADD COLUMN weight integer NOT NULL DEFAULT 1;
+
+-- Cap on the total weight of all votes, like max_voters for heads
+ALTER TABLE issues ADD COLUMN max_weight integer CHECK (max_weight > 0);
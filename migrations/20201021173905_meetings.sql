@@ -0,0 +1,15 @@
+-- Add migration script here
+-- Meetings group issues, issues outside of a meeting are shown to clients which haven't joined one
+CREATE TABLE IF NOT EXISTS meetings (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    title text NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now()
+);
+
+ALTER TABLE issues ADD COLUMN meeting_id UUID references meetings(id);
+
+-- Every meeting has its own active issue
+DROP INDEX issues_active_idx;
+CREATE UNIQUE INDEX issues_active_idx ON issues (
+    coalesce(meeting_id, '00000000-0000-0000-0000-000000000000')
+) WHERE active;
@@ -0,0 +1,8 @@
+-- Add migration script here
+-- Users eligible to vote on the issues of a meeting
+CREATE TABLE IF NOT EXISTS meeting_voters (
+    meeting_id UUID references meetings(id) ON DELETE CASCADE NOT NULL,
+    user_id UUID references users(id) ON DELETE CASCADE NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (meeting_id, user_id)
+);
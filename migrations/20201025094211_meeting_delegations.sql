@@ -0,0 +1,13 @@
+-- Add migration script here
+-- Delegations apply to one issue, to the issues of one meeting or to all issues
+ALTER TABLE delegations ADD COLUMN meeting_id UUID references meetings(id) ON DELETE CASCADE;
+ALTER TABLE delegations ADD CONSTRAINT delegations_single_scope
+    CHECK (issue_id IS NULL OR meeting_id IS NULL);
+
+-- Only one proxy per principal and scope
+DROP INDEX delegations_principal_issue_idx;
+CREATE UNIQUE INDEX delegations_principal_scope_idx ON delegations (
+    principal_id,
+    coalesce(issue_id, '00000000-0000-0000-0000-000000000000'),
+    coalesce(meeting_id, '00000000-0000-0000-0000-000000000000')
+);
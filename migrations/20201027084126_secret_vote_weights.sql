@@ -0,0 +1,34 @@
+-- Add migration script here
+-- Weight of secret ballots is only kept summed up for ballots with the same choices,
+-- so a ballot can't be told apart by the weight of its voter
+CREATE TABLE IF NOT EXISTS secret_vote_weights (
+    issue_id UUID references issues(id) ON DELETE CASCADE NOT NULL,
+    -- Alternatives of the ballots in the order they count, empty for abstentions
+    choices UUID[] NOT NULL,
+    ballots integer NOT NULL,
+    weight bigint NOT NULL,
+    PRIMARY KEY (issue_id, choices)
+);
+
+ALTER TABLE votes ALTER COLUMN weight DROP NOT NULL, ALTER COLUMN weight DROP DEFAULT;
+
+INSERT INTO secret_vote_weights (issue_id, choices, ballots, weight)
+SELECT votes.issue_id, ballot.choices, COUNT(*), SUM(votes.weight)
+FROM votes
+JOIN issues ON issues.id = votes.issue_id
+CROSS JOIN LATERAL (
+    SELECT CASE
+        WHEN votes.alternative_id IS NULL THEN ARRAY[]::UUID[]
+        WHEN issues.voting_method = 'approval' THEN
+            (SELECT array_agg(alternative_id ORDER BY alternative_id) FROM vote_choices WHERE vote_id = votes.id)
+        ELSE
+            coalesce(
+                (SELECT array_agg(alternative_id ORDER BY position) FROM vote_choices WHERE vote_id = votes.id),
+                ARRAY[votes.alternative_id]
+            )
+    END AS choices
+) ballot
+WHERE issues.secret
+GROUP BY votes.issue_id, ballot.choices;
+
+UPDATE votes SET weight = NULL FROM issues WHERE issues.id = votes.issue_id AND issues.secret;
@@ -0,0 +1,6 @@
+-- Add migration script here
+-- Issues of a meeting without a limit of their own are open to its current voter roll
+ALTER TABLE issues ALTER COLUMN max_voters DROP NOT NULL;
+ALTER TABLE issues ADD CONSTRAINT issues_max_voters_check
+    CHECK (max_voters IS NOT NULL OR meeting_id IS NOT NULL);
+
@@ -0,0 +1,9 @@
+-- Add migration script here
+-- Secret ballots wait here while their issue is in progress. Once it finishes they are
+-- published as votes all at once, so no vote shares a transaction with its receipt.
+CREATE TABLE IF NOT EXISTS secret_ballots (
+    issue_id UUID references issues(id) ON DELETE CASCADE NOT NULL,
+    -- Alternatives in the order they count, empty for abstentions
+    choices UUID[] NOT NULL
+);
+CREATE INDEX secret_ballots_issue_idx ON secret_ballots (issue_id);
@@ -0,0 +1,3 @@
+-- Add migration script here
+-- Queued secret ballots carry their weight until it is summed up when they are published
+ALTER TABLE secret_ballots ADD COLUMN weight integer NOT NULL;
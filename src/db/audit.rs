@@ -0,0 +1,40 @@
+use super::{issue::IssueId, user::UserId};
+use color_eyre::eyre::{Report, WrapErr};
+use sqlx::{Executor, Postgres};
+
+#[derive(Clone, PartialEq, Eq, Debug, sqlx::Type)]
+#[sqlx(rename = "text")]
+#[sqlx(rename_all = "snake_case")]
+pub enum InternalAuditAction {
+    /// An admin voted for another user in demo mode
+    DemoVote,
+    /// A finished issue was reset, removing its votes and result
+    ResetFinishedIssue,
+}
+
+#[derive(Clone, Debug)]
+pub struct NewAuditEntry {
+    pub actor_id: UserId,
+    pub action: InternalAuditAction,
+    /// User affected by the action
+    pub user_id: Option<UserId>,
+    pub issue_id: Option<IssueId>,
+}
+
+/// Records an audit entry, use a transaction to tie it to the audited change
+pub async fn insert_audit(
+    executor: impl Executor<'_, Database = Postgres>,
+    entry: NewAuditEntry,
+) -> Result<(), Report> {
+    sqlx::query!(
+        "INSERT INTO audit_log (actor_id, action, user_id, issue_id) VALUES ($1, $2, $3, $4)",
+        entry.actor_id.0,
+        entry.action as InternalAuditAction,
+        entry.user_id.map(|id| id.0),
+        entry.issue_id.map(|id| id.0),
+    )
+    .execute(executor)
+    .await
+    .wrap_err("Got error while recording audit entry")?;
+    Ok(())
+}
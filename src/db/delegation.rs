@@ -0,0 +1,191 @@
+use super::{issue::IssueId, meeting::MeetingId, user::UserId, DbExecutor};
+use crate::{span::AsyncSpanHandler, span_message_async_impl};
+use actix::prelude::*;
+use actix_interop::with_ctx;
+use color_eyre::eyre::{Report, WrapErr};
+use serde::{Deserialize, Serialize};
+use sqlx::{types::Uuid, Done, Executor, Postgres};
+use std::fmt;
+use tracing::{debug, instrument};
+
+#[derive(Clone, Hash, PartialEq, Eq, Debug, Deserialize, Serialize, sqlx::Type)]
+#[sqlx(transparent)]
+pub struct DelegationId(pub Uuid);
+
+impl DelegationId {
+    pub fn new() -> Self {
+        Self(Uuid::new_v4())
+    }
+}
+
+impl Default for DelegationId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Permission for the proxy to vote for the principal
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct InternalDelegation {
+    pub id: DelegationId,
+    pub principal_id: UserId,
+    pub proxy_id: UserId,
+    /// No issue means the delegation applies to all issues of the meeting
+    pub issue_id: Option<IssueId>,
+    /// No issue and no meeting means the delegation applies to all issues
+    pub meeting_id: Option<MeetingId>,
+}
+
+/// Postgres error codes for constraint violations
+const FOREIGN_KEY_VIOLATION: &str = "23503";
+const UNIQUE_VIOLATION: &str = "23505";
+
+#[derive(Debug)]
+pub enum DelegationError {
+    SelfDelegation,
+    /// A delegation is either for an issue or for a meeting
+    IssueAndMeeting,
+    UnknownUserOrIssue,
+    AlreadyDelegated,
+    NotFound,
+}
+
+impl fmt::Display for DelegationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DelegationError::SelfDelegation => write!(f, "Users can't delegate to themselves"),
+            DelegationError::IssueAndMeeting => {
+                write!(f, "Delegations can't be limited to an issue and a meeting")
+            }
+            DelegationError::UnknownUserOrIssue => {
+                write!(f, "Proxy, issue or meeting doesn't exist")
+            }
+            DelegationError::AlreadyDelegated => {
+                write!(
+                    f,
+                    "A proxy has already been granted for this issue or meeting"
+                )
+            }
+            DelegationError::NotFound => write!(f, "Delegation not found"),
+        }
+    }
+}
+
+impl std::error::Error for DelegationError {}
+
+#[derive(Message, Clone, Debug)]
+#[rtype(result = "Result<InternalDelegation, Report>")]
+pub struct GrantDelegation {
+    pub principal_id: UserId,
+    pub proxy_id: UserId,
+    pub issue_id: Option<IssueId>,
+    pub meeting_id: Option<MeetingId>,
+}
+
+#[async_trait::async_trait]
+impl AsyncSpanHandler<GrantDelegation> for DbExecutor {
+    #[instrument]
+    async fn handle(msg: GrantDelegation) -> Result<InternalDelegation, Report> {
+        debug!("Granting delegation");
+        if msg.principal_id == msg.proxy_id {
+            return Err(DelegationError::SelfDelegation.into());
+        }
+        if msg.issue_id.is_some() && msg.meeting_id.is_some() {
+            return Err(DelegationError::IssueAndMeeting.into());
+        }
+        let pool = with_ctx(|a: &mut DbExecutor, _| a.pool());
+        sqlx::query_as!(
+            InternalDelegation,
+            r#"
+            INSERT INTO delegations (principal_id, proxy_id, issue_id, meeting_id) VALUES ($1, $2, $3, $4)
+            RETURNING
+                id as "id: _",
+                principal_id as "principal_id: _",
+                proxy_id as "proxy_id: _",
+                issue_id as "issue_id: _",
+                meeting_id as "meeting_id: _"
+            "#,
+            msg.principal_id.0,
+            msg.proxy_id.0,
+            msg.issue_id.map(|id| id.0),
+            msg.meeting_id.map(|id| id.0),
+        )
+        .fetch_one(&pool)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::Database(err) if err.code().as_deref() == Some(UNIQUE_VIOLATION) => {
+                DelegationError::AlreadyDelegated.into()
+            }
+            sqlx::Error::Database(err) if err.code().as_deref() == Some(FOREIGN_KEY_VIOLATION) => {
+                DelegationError::UnknownUserOrIssue.into()
+            }
+            e => Report::new(e).wrap_err("Got error while adding delegation to db"),
+        })
+    }
+}
+span_message_async_impl!(GrantDelegation, DbExecutor);
+
+#[derive(Message, Clone, Debug)]
+#[rtype(result = "Result<(), Report>")]
+pub struct RevokeDelegation {
+    pub principal_id: UserId,
+    pub issue_id: Option<IssueId>,
+    pub meeting_id: Option<MeetingId>,
+}
+
+#[async_trait::async_trait]
+impl AsyncSpanHandler<RevokeDelegation> for DbExecutor {
+    #[instrument]
+    async fn handle(msg: RevokeDelegation) -> Result<(), Report> {
+        debug!("Revoking delegation");
+        let pool = with_ctx(|a: &mut DbExecutor, _| a.pool());
+        let deleted = sqlx::query!(
+            r#"
+            DELETE FROM delegations
+            WHERE principal_id = $1 AND issue_id IS NOT DISTINCT FROM $2 AND meeting_id IS NOT DISTINCT FROM $3
+            "#,
+            msg.principal_id.0,
+            msg.issue_id.map(|id| id.0),
+            msg.meeting_id.map(|id| id.0),
+        )
+        .execute(&pool)
+        .await
+        .wrap_err("Got error while revoking delegation")?
+        .rows_affected();
+        if deleted == 0 {
+            return Err(DelegationError::NotFound.into());
+        }
+        Ok(())
+    }
+}
+span_message_async_impl!(RevokeDelegation, DbExecutor);
+
+/// Whether the proxy may vote for the principal on the issue of the meeting.
+/// A delegation for the issue takes precedence over one for the meeting,
+/// which takes precedence over one for all issues.
+pub async fn has_delegation(
+    executor: impl Executor<'_, Database = Postgres>,
+    principal_id: &UserId,
+    proxy_id: &UserId,
+    issue_id: &IssueId,
+    meeting_id: Option<&MeetingId>,
+) -> Result<bool, Report> {
+    let delegation = sqlx::query!(
+        r#"
+        SELECT proxy_id as "proxy_id: UserId" FROM delegations
+        WHERE principal_id = $1 AND (
+            issue_id = $2
+            OR (issue_id IS NULL AND (meeting_id = $3 OR meeting_id IS NULL))
+        )
+        ORDER BY issue_id IS NULL, meeting_id IS NULL
+        LIMIT 1
+        "#,
+        principal_id.0,
+        issue_id.0,
+        meeting_id.map(|id| id.0),
+    )
+    .fetch_optional(executor)
+    .await
+    .wrap_err("Got error while checking delegation")?;
+    Ok(delegation.map_or(false, |delegation| &delegation.proxy_id == proxy_id))
+}
@@ -1,12 +1,20 @@
-use super::{alternative::InternalAlternative, DbExecutor};
+use super::{
+    alternative::InternalAlternative,
+    audit::{insert_audit, InternalAuditAction, NewAuditEntry},
+    meeting::{count_voters, MeetingError, MeetingId},
+    user::UserId,
+    vote::publish_secret_ballots,
+    DbExecutor,
+};
 use crate::async_message_handler_with_span;
 use crate::span::AsyncSpanHandler;
-use crate::websocket::{Alternative, Issue};
+use crate::websocket::{Alternative, IncomingIssue};
 use actix::prelude::*;
 use actix_interop::with_ctx;
 use color_eyre::eyre::{Report, WrapErr};
 use serde::{Deserialize, Serialize};
 use sqlx::{types::Uuid, Executor, Postgres};
+use std::fmt;
 use tracing::{debug, instrument};
 
 #[derive(Clone, Hash, PartialEq, Eq, Debug, Deserialize, Serialize, sqlx::Type)]
@@ -25,7 +33,7 @@ impl Default for IssueId {
     }
 }
 
-#[derive(Clone, PartialEq, Debug, sqlx::Type)]
+#[derive(Clone, PartialEq, Eq, Debug, sqlx::Type)]
 #[sqlx(rename = "text")]
 #[sqlx(rename_all = "snake_case")]
 pub enum InternalIssueState {
@@ -34,19 +42,190 @@ pub enum InternalIssueState {
     Finished,
 }
 
+impl InternalIssueState {
+    /// Returns the state the issue ends up in after the transition,
+    /// or an error if the transition isn't allowed from the current state
+    pub fn transition(&self, transition: IssueTransition) -> Result<Self, IssueError> {
+        use InternalIssueState::*;
+        match (self, &transition) {
+            (NotStarted, IssueTransition::Start) => Ok(InProgress),
+            (InProgress, IssueTransition::Finish) => Ok(Finished),
+            (InProgress, IssueTransition::Reset) | (Finished, IssueTransition::Reset) => {
+                Ok(NotStarted)
+            }
+            _ => Err(IssueError::InvalidTransition(self.clone(), transition)),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, sqlx::Type)]
+#[sqlx(rename = "text")]
+#[sqlx(rename_all = "snake_case")]
+pub enum InternalVotingMethod {
+    /// Every voter picks a single alternative
+    Plurality,
+    /// Voters rank the alternatives, counted by instant runoff
+    Ranked,
+    /// Voters select any number of alternatives within the issue's limits
+    Approval,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, sqlx::Type)]
+#[sqlx(rename = "text")]
+#[sqlx(rename_all = "snake_case")]
+pub enum InternalMajorityKind {
+    Simple,
+    Absolute,
+    Qualified,
+}
+
+/// Votes the winning alternative needs for the issue to pass
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum InternalMajority {
+    /// More votes than any other alternative
+    Simple,
+    /// More than half of the counted votes
+    Absolute,
+    /// At least the given fraction of the counted votes
+    Qualified { numerator: i32, denominator: i32 },
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct InternalDecisionRules {
+    pub majority: InternalMajority,
+    /// Share of max voters which has to vote, abstentions included
+    pub quorum_percent: i32,
+    /// Abstentions are counted as votes cast for the majority
+    pub count_abstentions: bool,
+}
+
+impl Default for InternalDecisionRules {
+    fn default() -> Self {
+        Self {
+            majority: InternalMajority::Simple,
+            quorum_percent: 0,
+            count_abstentions: false,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum IssueTransition {
+    Start,
+    Finish,
+    Reset,
+}
+
+#[derive(Debug)]
+pub enum IssueError {
+    NotFound(IssueId),
+    InvalidTransition(InternalIssueState, IssueTransition),
+    InvalidRules(&'static str),
+    /// Max voters can't be derived from an empty voter roll
+    NoEligibleVoters,
+    /// Resetting a finished issue discards its result and must be confirmed
+    ResetNotConfirmed(IssueId),
+}
+
+impl fmt::Display for IssueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IssueError::NotFound(id) => write!(f, "Issue {} not found", id.0),
+            IssueError::InvalidTransition(state, transition) => {
+                write!(f, "Can't {:?} issue while it is {:?}", transition, state)
+            }
+            IssueError::InvalidRules(reason) => write!(f, "Invalid decision rules: {}", reason),
+            IssueError::NoEligibleVoters => {
+                write!(
+                    f,
+                    "Nobody may vote on the issue, add voters or set max voters"
+                )
+            }
+            IssueError::ResetNotConfirmed(id) => {
+                write!(f, "Resetting finished issue {} must be confirmed", id.0)
+            }
+        }
+    }
+}
+
+impl std::error::Error for IssueError {}
+
 #[derive(Clone, Debug)]
 pub struct InternalIssue {
     pub id: IssueId,
     pub title: String,
     pub description: String,
     pub state: InternalIssueState,
+    /// Issues of a meeting without a limit of their own take the size of its current voter roll
     pub max_voters: i32,
     pub show_distribution: bool,
+    pub allow_vote_change: bool,
+    /// Votes are stored and broadcast without the voter
+    pub secret: bool,
+    pub voting_method: InternalVotingMethod,
+    /// Limits of the alternatives selected on approval ballots
+    pub min_choices: i32,
+    pub max_choices: Option<i32>,
+    pub majority: InternalMajorityKind,
+    pub qualified_numerator: Option<i32>,
+    pub qualified_denominator: Option<i32>,
+    pub quorum_percent: i32,
+    pub count_abstentions: bool,
+    /// Cap on the summed weight of all votes
+    pub max_weight: Option<i32>,
+    /// Issues outside of any meeting are shown to clients which haven't joined one
+    pub meeting_id: Option<MeetingId>,
+}
+
+impl InternalIssue {
+    pub fn rules(&self) -> InternalDecisionRules {
+        let majority = match (
+            &self.majority,
+            self.qualified_numerator,
+            self.qualified_denominator,
+        ) {
+            (InternalMajorityKind::Simple, _, _) => InternalMajority::Simple,
+            (InternalMajorityKind::Absolute, _, _) => InternalMajority::Absolute,
+            (InternalMajorityKind::Qualified, Some(numerator), Some(denominator)) => {
+                InternalMajority::Qualified {
+                    numerator,
+                    denominator,
+                }
+            }
+            // Prevented by a check constraint
+            (InternalMajorityKind::Qualified, _, _) => InternalMajority::Absolute,
+        };
+        InternalDecisionRules {
+            majority,
+            quorum_percent: self.quorum_percent,
+            count_abstentions: self.count_abstentions,
+        }
+    }
+}
+
+fn validate_rules(rules: &InternalDecisionRules) -> Result<(), IssueError> {
+    if let InternalMajority::Qualified {
+        numerator,
+        denominator,
+    } = rules.majority
+    {
+        if numerator <= 0 || denominator < numerator {
+            return Err(IssueError::InvalidRules(
+                "qualified majority must be a fraction between 0 and 1",
+            ));
+        }
+    }
+    if !(0..=100).contains(&rules.quorum_percent) {
+        return Err(IssueError::InvalidRules(
+            "quorum must be between 0 and 100 percent",
+        ));
+    }
+    Ok(())
 }
 
 #[derive(Message, Clone)]
 #[rtype(result = "Result<Option<InternalIssue>, Report>")]
-pub struct IssueById(IssueId);
+pub struct IssueById(pub IssueId);
 
 async_message_handler_with_span!({
     impl AsyncSpanHandler<IssueById> for DbExecutor {
@@ -57,7 +236,8 @@ async_message_handler_with_span!({
             debug!("Retrieving issue by id {id}", id = uuid);
             let user = sqlx::query_as!(InternalIssue,
                     r#"
-                    SELECT id as "id: _", title, description, state as "state: _", max_voters, show_distribution
+                    SELECT id as "id: _", title, description, state as "state: _", COALESCE(max_voters, (SELECT COUNT(*) FROM meeting_voters WHERE meeting_voters.meeting_id = issues.meeting_id)::integer) as "max_voters!", show_distribution, allow_vote_change, secret,
+                    voting_method as "voting_method: _", min_choices, max_choices, majority as "majority: _", qualified_numerator, qualified_denominator, quorum_percent, count_abstentions, max_weight, meeting_id as "meeting_id: _"
                     FROM issues WHERE id = $1
                     "#, uuid
                 ).fetch_optional(&pool).await?;
@@ -67,21 +247,30 @@ async_message_handler_with_span!({
     }
 });
 
+/// Active issue of the meeting, or of the issues outside of any meeting
 #[derive(Message, Clone)]
 #[rtype(result = "Result<Option<InternalIssue>, Report>")]
-pub struct ActiveIssue();
+pub struct ActiveIssue(pub Option<MeetingId>);
 
 async_message_handler_with_span!({
     impl AsyncSpanHandler<ActiveIssue> for DbExecutor {
-        async fn handle(_msg: ActiveIssue) -> Result<Option<InternalIssue>, Report> {
+        async fn handle(msg: ActiveIssue) -> Result<Option<InternalIssue>, Report> {
+            let ActiveIssue(meeting_id) = msg;
             let pool = with_ctx(|a: &mut DbExecutor, _| a.pool());
             debug!("Retrieving active issue");
+            // Falls back to the oldest issue if no issue has been activated,
+            // so creating an issue never replaces the one clients are shown
             let user = sqlx::query_as!(
                     InternalIssue,
                     r#"
-                    SELECT id as "id: _", title, description, state as "state: _", max_voters, show_distribution
+                    SELECT id as "id: _", title, description, state as "state: _", COALESCE(max_voters, (SELECT COUNT(*) FROM meeting_voters WHERE meeting_voters.meeting_id = issues.meeting_id)::integer) as "max_voters!", show_distribution, allow_vote_change, secret,
+                    voting_method as "voting_method: _", min_choices, max_choices, majority as "majority: _", qualified_numerator, qualified_denominator, quorum_percent, count_abstentions, max_weight, meeting_id as "meeting_id: _"
                     FROM issues
-                    "#
+                    WHERE meeting_id IS NOT DISTINCT FROM $1
+                    ORDER BY active DESC, created_at ASC
+                    LIMIT 1
+                    "#,
+                    meeting_id.map(|id| id.0)
                 )
                 .fetch_optional(&pool)
                 .await?;
@@ -91,53 +280,158 @@ async_message_handler_with_span!({
 });
 
 #[derive(Message, Clone, Debug)]
-#[rtype(result = "Result<Option<InternalIssue>, Report>")]
-pub struct NewIssue(pub Issue);
+#[rtype(result = "Result<InternalIssue, Report>")]
+pub struct SetActiveIssue(pub IssueId);
+
+#[async_trait::async_trait]
+impl AsyncSpanHandler<SetActiveIssue> for DbExecutor {
+    #[instrument]
+    async fn handle(msg: SetActiveIssue) -> Result<InternalIssue, Report> {
+        let SetActiveIssue(issue_id) = msg;
+        let pool = with_ctx(|a: &mut DbExecutor, _| a.pool());
+        debug!("Setting active issue in db");
+
+        let mut tx = pool.begin().await?;
+
+        // Only the active issue of the same meeting is replaced
+        sqlx::query!(
+            r#"
+            UPDATE issues SET active = false
+            WHERE active AND meeting_id IS NOT DISTINCT FROM (SELECT meeting_id FROM issues WHERE id = $1)
+            "#,
+            issue_id.0
+        )
+        .execute(&mut tx)
+        .await
+        .wrap_err("Got error while deactivating issue")?;
+
+        let issue = sqlx::query_as!(
+            InternalIssue,
+            r#"
+                UPDATE issues SET active = true
+                WHERE id = $1
+                RETURNING id as "id: _", title, description, state as "state: _", COALESCE(max_voters, (SELECT COUNT(*) FROM meeting_voters WHERE meeting_voters.meeting_id = issues.meeting_id)::integer) as "max_voters!", show_distribution, allow_vote_change, secret,
+                    voting_method as "voting_method: _", min_choices, max_choices, majority as "majority: _", qualified_numerator, qualified_denominator, quorum_percent, count_abstentions, max_weight, meeting_id as "meeting_id: _"
+                "#,
+            issue_id.0
+        )
+        .fetch_optional(&mut tx)
+        .await
+        .wrap_err("Got error while activating issue")?
+        .ok_or(IssueError::NotFound(issue_id))?;
+
+        tx.commit().await?;
+        Ok(issue)
+    }
+}
+crate::span_message_async_impl!(SetActiveIssue, DbExecutor);
+
+#[derive(Message, Clone, Debug)]
+#[rtype(result = "Result<(InternalIssue, Vec<InternalAlternative>), Report>")]
+pub struct NewIssue(pub IncomingIssue);
+
+/// Postgres error code for foreign key violations
+const FOREIGN_KEY_VIOLATION: &str = "23503";
+
+/// Limit of issues outside of any meeting which don't set one
+const DEFAULT_MAX_VOTERS: i32 = 1000;
 
 async fn insert_issue(
-    executor: impl Executor<'_, Database = Postgres>,
-    data: &Issue,
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    data: &IncomingIssue,
 ) -> Result<InternalIssue, Report> {
-    let issue_state = match &data.state {
-        Some(state) => match state {
-            crate::websocket::IssueState::NotStarted => "not_started",
-            // crate::websocket::IssueState::NotStarted => crate::db::issue::InternalIssueState::NotStarted,
-            crate::websocket::IssueState::InProgress => "in_progress",
-            // crate::websocket::IssueState::InProgress => crate::db::issue::InternalIssueState::InProgress,
-            crate::websocket::IssueState::Finished => "finished",
-            // crate::websocket::IssueState::Finished => crate::db::issue::InternalIssueState::Finished,
-        },
-        None => "not_started",
+    // Meeting issues without a limit of their own follow their voter roll, so voters can still be added
+    let max_voters = match (data.max_voters, &data.meeting_id) {
+        (Some(max_voters), _) => Some(max_voters),
+        (None, Some(meeting_id)) => {
+            if count_voters(&mut *tx, meeting_id).await? == 0 {
+                return Err(IssueError::NoEligibleVoters.into());
+            }
+            None
+        }
+        (None, None) => Some(DEFAULT_MAX_VOTERS),
     };
 
-    // @ToDo: Get available voters
-    let max_voters = match data.max_voters {
-        Some(num) => num,
-        None => 1000,
+    let min_choices = data.min_choices.unwrap_or(1);
+    if min_choices < 1 || data.max_choices.map_or(false, |max| max < min_choices) {
+        return Err(IssueError::InvalidRules("choice limits must satisfy 1 <= min <= max").into());
+    }
+    if data.max_weight.map_or(false, |max| max < 1) {
+        return Err(IssueError::InvalidRules("max weight must be positive").into());
+    }
+
+    let rules = InternalDecisionRules::from(data.rules.clone());
+    validate_rules(&rules)?;
+    let (majority, qualified_numerator, qualified_denominator) = match rules.majority {
+        InternalMajority::Simple => (InternalMajorityKind::Simple, None, None),
+        InternalMajority::Absolute => (InternalMajorityKind::Absolute, None, None),
+        InternalMajority::Qualified {
+            numerator,
+            denominator,
+        } => (
+            InternalMajorityKind::Qualified,
+            Some(numerator),
+            Some(denominator),
+        ),
     };
 
     sqlx::query_as!(
         InternalIssue,
         r#"
-                INSERT INTO issues ( title, description, state, max_voters, show_distribution )
-                VALUES ( $1, $2, $3, $4, $5 )
+                INSERT INTO issues (
+                    title, description, state, max_voters, show_distribution, allow_vote_change, secret,
+                    voting_method, min_choices, max_choices, majority, qualified_numerator,
+                    qualified_denominator, quorum_percent, count_abstentions, max_weight, meeting_id
+                )
+                VALUES ( $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17 )
                 RETURNING
                     id as "id: _",
                     title as "title: _",
                     description as "description: _",
                     state as "state: _",
-                    max_voters as "max_voters: _",
-                    show_distribution as "show_distribution: _"
+                    COALESCE(max_voters, (SELECT COUNT(*) FROM meeting_voters WHERE meeting_voters.meeting_id = issues.meeting_id)::integer) as "max_voters!",
+                    show_distribution as "show_distribution: _",
+                    allow_vote_change as "allow_vote_change: _",
+                    secret as "secret: _",
+                    voting_method as "voting_method: _",
+                    min_choices,
+                    max_choices,
+                    majority as "majority: _",
+                    qualified_numerator,
+                    qualified_denominator,
+                    quorum_percent,
+                    count_abstentions,
+                    max_weight,
+                    meeting_id as "meeting_id: _"
                 "#,
         data.title,
         data.description,
-        issue_state,
+        InternalIssueState::NotStarted as InternalIssueState,
         max_voters,
-        data.show_distribution
+        data.show_distribution,
+        data.allow_vote_change,
+        data.secret,
+        InternalVotingMethod::from(data.voting_method) as InternalVotingMethod,
+        min_choices,
+        data.max_choices,
+        majority as InternalMajorityKind,
+        qualified_numerator,
+        qualified_denominator,
+        rules.quorum_percent,
+        rules.count_abstentions,
+        data.max_weight,
+        data.meeting_id.as_ref().map(|id| id.0)
     )
-    .fetch_one(executor)
+    .fetch_one(&mut *tx)
     .await
-    .wrap_err("Got error while adding new issue to db")
+    .map_err(|e| match (e, &data.meeting_id) {
+        (sqlx::Error::Database(err), Some(meeting_id))
+            if err.code().as_deref() == Some(FOREIGN_KEY_VIOLATION) =>
+        {
+            MeetingError::NotFound(meeting_id.clone()).into()
+        }
+        (e, _) => Report::new(e).wrap_err("Got error while adding new issue to db"),
+    })
 }
 
 async fn insert_alternative(
@@ -166,20 +460,129 @@ async fn insert_alternative(
 #[async_trait::async_trait]
 impl AsyncSpanHandler<NewIssue> for DbExecutor {
     #[instrument]
-    async fn handle(_msg: NewIssue) -> Result<Option<InternalIssue>, Report> {
+    async fn handle(msg: NewIssue) -> Result<(InternalIssue, Vec<InternalAlternative>), Report> {
         let pool = with_ctx(|a: &mut DbExecutor, _| a.pool());
         debug!("Creating new issue in db");
 
         let mut tx = pool.begin().await?;
 
-        let i = insert_issue(&mut tx, &_msg.0).await?;
+        let i = insert_issue(&mut tx, &msg.0).await?;
 
-        for alt in _msg.0.alternatives.iter() {
-            insert_alternative(&mut tx, alt, &i).await?;
+        let mut alternatives = Vec::with_capacity(msg.0.alternatives.len());
+        for alt in msg.0.alternatives.iter() {
+            alternatives.push(insert_alternative(&mut tx, alt, &i).await?);
         }
 
         tx.commit().await?;
-        Ok(Some(i))
+        Ok((i, alternatives))
     }
 }
 crate::span_message_async_impl!(NewIssue, DbExecutor);
+
+#[derive(Message, Clone, Debug)]
+#[rtype(result = "Result<InternalIssue, Report>")]
+pub struct UpdateIssueState {
+    pub issue_id: IssueId,
+    pub transition: IssueTransition,
+    /// User changing the state, audited when a finished issue is reset
+    pub actor_id: UserId,
+    /// Required to reset a finished issue
+    pub confirmed: bool,
+}
+
+#[async_trait::async_trait]
+impl AsyncSpanHandler<UpdateIssueState> for DbExecutor {
+    #[instrument]
+    async fn handle(msg: UpdateIssueState) -> Result<InternalIssue, Report> {
+        let UpdateIssueState {
+            issue_id,
+            transition,
+            actor_id,
+            confirmed,
+        } = msg;
+        let pool = with_ctx(|a: &mut DbExecutor, _| a.pool());
+        debug!("Updating issue state in db");
+
+        let mut tx = pool.begin().await?;
+
+        // Lock the issue so concurrent votes and transitions see a consistent state
+        let state = sqlx::query!(
+            r#"SELECT state as "state: InternalIssueState" FROM issues WHERE id = $1 FOR UPDATE"#,
+            issue_id.0
+        )
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or_else(|| IssueError::NotFound(issue_id.clone()))?
+        .state;
+
+        let new_state = state.transition(transition.clone())?;
+
+        if transition == IssueTransition::Reset && state == InternalIssueState::Finished {
+            if !confirmed {
+                return Err(IssueError::ResetNotConfirmed(issue_id).into());
+            }
+            insert_audit(
+                &mut tx,
+                NewAuditEntry {
+                    actor_id,
+                    action: InternalAuditAction::ResetFinishedIssue,
+                    user_id: None,
+                    issue_id: Some(issue_id.clone()),
+                },
+            )
+            .await?;
+        }
+
+        if transition == IssueTransition::Reset {
+            sqlx::query!("DELETE FROM votes WHERE issue_id = $1", issue_id.0)
+                .execute(&mut tx)
+                .await
+                .wrap_err("Got error while removing votes for reset issue")?;
+            sqlx::query!(
+                "DELETE FROM ballot_receipts WHERE issue_id = $1",
+                issue_id.0
+            )
+            .execute(&mut tx)
+            .await
+            .wrap_err("Got error while removing ballot receipts for reset issue")?;
+            sqlx::query!(
+                "DELETE FROM secret_vote_weights WHERE issue_id = $1",
+                issue_id.0
+            )
+            .execute(&mut tx)
+            .await
+            .wrap_err("Got error while removing secret ballot weights for reset issue")?;
+            sqlx::query!("DELETE FROM secret_ballots WHERE issue_id = $1", issue_id.0)
+                .execute(&mut tx)
+                .await
+                .wrap_err("Got error while removing queued secret ballots for reset issue")?;
+            sqlx::query!("DELETE FROM issue_results WHERE issue_id = $1", issue_id.0)
+                .execute(&mut tx)
+                .await
+                .wrap_err("Got error while removing result for reset issue")?;
+        }
+
+        let issue = sqlx::query_as!(
+            InternalIssue,
+            r#"
+                UPDATE issues SET state = $2
+                WHERE id = $1
+                RETURNING id as "id: _", title, description, state as "state: _", COALESCE(max_voters, (SELECT COUNT(*) FROM meeting_voters WHERE meeting_voters.meeting_id = issues.meeting_id)::integer) as "max_voters!", show_distribution, allow_vote_change, secret,
+                    voting_method as "voting_method: _", min_choices, max_choices, majority as "majority: _", qualified_numerator, qualified_denominator, quorum_percent, count_abstentions, max_weight, meeting_id as "meeting_id: _"
+                "#,
+            issue_id.0,
+            new_state as InternalIssueState
+        )
+        .fetch_one(&mut tx)
+        .await
+        .wrap_err("Got error while updating issue state")?;
+
+        if issue.state == InternalIssueState::Finished && issue.secret {
+            publish_secret_ballots(&mut tx, &issue_id, &issue.voting_method).await?;
+        }
+
+        tx.commit().await?;
+        Ok(issue)
+    }
+}
+crate::span_message_async_impl!(UpdateIssueState, DbExecutor);
@@ -0,0 +1,224 @@
+use super::{user::UserId, DbExecutor};
+use crate::{span::AsyncSpanHandler, span_message_async_impl};
+use actix::prelude::*;
+use actix_interop::with_ctx;
+use color_eyre::eyre::{Report, WrapErr};
+use serde::{Deserialize, Serialize};
+use sqlx::{types::Uuid, Executor, Postgres};
+use std::fmt;
+use tracing::{debug, instrument};
+
+#[derive(Clone, Hash, PartialEq, Eq, Debug, Deserialize, Serialize, sqlx::Type)]
+#[sqlx(transparent)]
+pub struct MeetingId(pub Uuid);
+
+impl MeetingId {
+    pub fn new() -> Self {
+        Self(Uuid::new_v4())
+    }
+}
+
+impl Default for MeetingId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct InternalMeeting {
+    pub id: MeetingId,
+    pub title: String,
+}
+
+/// Postgres error code for foreign key violations
+const FOREIGN_KEY_VIOLATION: &str = "23503";
+
+#[derive(Debug)]
+pub enum MeetingError {
+    NotFound(MeetingId),
+    UnknownUser(UserId),
+}
+
+impl fmt::Display for MeetingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeetingError::NotFound(id) => write!(f, "Meeting {} not found", id.0),
+            MeetingError::UnknownUser(id) => write!(f, "User {} not found", id.as_string()),
+        }
+    }
+}
+
+impl std::error::Error for MeetingError {}
+
+#[derive(Message, Clone, Debug)]
+#[rtype(result = "Result<InternalMeeting, Report>")]
+pub struct NewMeeting {
+    pub title: String,
+}
+
+#[async_trait::async_trait]
+impl AsyncSpanHandler<NewMeeting> for DbExecutor {
+    #[instrument]
+    async fn handle(msg: NewMeeting) -> Result<InternalMeeting, Report> {
+        debug!("Adding meeting");
+        let pool = with_ctx(|a: &mut DbExecutor, _| a.pool());
+        sqlx::query_as!(
+            InternalMeeting,
+            r#"INSERT INTO meetings (title) VALUES ($1) RETURNING id as "id: _", title"#,
+            msg.title
+        )
+        .fetch_one(&pool)
+        .await
+        .wrap_err("Got error while adding meeting to db")
+    }
+}
+span_message_async_impl!(NewMeeting, DbExecutor);
+
+#[derive(Message, Clone, Debug)]
+#[rtype(result = "Result<InternalMeeting, Report>")]
+pub struct MeetingById(pub MeetingId);
+
+#[async_trait::async_trait]
+impl AsyncSpanHandler<MeetingById> for DbExecutor {
+    #[instrument]
+    async fn handle(msg: MeetingById) -> Result<InternalMeeting, Report> {
+        let MeetingById(meeting_id) = msg;
+        debug!("Retrieving meeting");
+        let pool = with_ctx(|a: &mut DbExecutor, _| a.pool());
+        get_meeting(&pool, meeting_id).await
+    }
+}
+span_message_async_impl!(MeetingById, DbExecutor);
+
+async fn get_meeting(
+    executor: impl Executor<'_, Database = Postgres>,
+    meeting_id: MeetingId,
+) -> Result<InternalMeeting, Report> {
+    let meeting = sqlx::query_as!(
+        InternalMeeting,
+        r#"SELECT id as "id: _", title FROM meetings WHERE id = $1"#,
+        meeting_id.0
+    )
+    .fetch_optional(executor)
+    .await
+    .wrap_err("Got error while retrieving meeting")?;
+    Ok(meeting.ok_or(MeetingError::NotFound(meeting_id))?)
+}
+
+// Voter roll
+
+/// Number of users on the voter roll of the meeting
+pub async fn count_voters(
+    executor: impl Executor<'_, Database = Postgres>,
+    meeting_id: &MeetingId,
+) -> Result<i64, Report> {
+    let row = sqlx::query!(
+        r#"
+        SELECT (SELECT COUNT(*) FROM meeting_voters WHERE meeting_id = meetings.id) as "count!"
+        FROM meetings WHERE id = $1
+        "#,
+        meeting_id.0
+    )
+    .fetch_optional(executor)
+    .await
+    .wrap_err("Got error while counting voters of meeting")?
+    .ok_or_else(|| MeetingError::NotFound(meeting_id.clone()))?;
+    Ok(row.count)
+}
+
+/// Whether the user is on the voter roll of the meeting
+pub async fn on_voter_roll(
+    executor: impl Executor<'_, Database = Postgres>,
+    meeting_id: &MeetingId,
+    user_id: &UserId,
+) -> Result<bool, Report> {
+    let row = sqlx::query!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM meeting_voters WHERE meeting_id = $1 AND user_id = $2
+        ) as "on_roll!"
+        "#,
+        meeting_id.0,
+        user_id.0
+    )
+    .fetch_one(executor)
+    .await
+    .wrap_err("Got error while checking voter roll of meeting")?;
+    Ok(row.on_roll)
+}
+
+#[derive(Message, Clone, Debug)]
+#[rtype(result = "Result<bool, Report>")]
+pub struct OnVoterRoll(pub MeetingId, pub UserId);
+
+#[async_trait::async_trait]
+impl AsyncSpanHandler<OnVoterRoll> for DbExecutor {
+    #[instrument]
+    async fn handle(msg: OnVoterRoll) -> Result<bool, Report> {
+        let OnVoterRoll(meeting_id, user_id) = msg;
+        debug!("Checking voter roll of meeting");
+        let pool = with_ctx(|a: &mut DbExecutor, _| a.pool());
+        on_voter_roll(&pool, &meeting_id, &user_id).await
+    }
+}
+span_message_async_impl!(OnVoterRoll, DbExecutor);
+
+/// Puts the user on the voter roll of the meeting, adding a user twice has no effect
+#[derive(Message, Clone, Debug)]
+#[rtype(result = "Result<(), Report>")]
+pub struct AddVoter(pub MeetingId, pub UserId);
+
+#[async_trait::async_trait]
+impl AsyncSpanHandler<AddVoter> for DbExecutor {
+    #[instrument]
+    async fn handle(msg: AddVoter) -> Result<(), Report> {
+        let AddVoter(meeting_id, user_id) = msg;
+        debug!("Adding voter to meeting");
+        let pool = with_ctx(|a: &mut DbExecutor, _| a.pool());
+        get_meeting(&pool, meeting_id.clone()).await?;
+        sqlx::query!(
+            r#"
+            INSERT INTO meeting_voters (meeting_id, user_id) VALUES ($1, $2)
+            ON CONFLICT DO NOTHING
+            "#,
+            meeting_id.0,
+            user_id.0
+        )
+        .execute(&pool)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::Database(err) if err.code().as_deref() == Some(FOREIGN_KEY_VIOLATION) => {
+                MeetingError::UnknownUser(user_id).into()
+            }
+            e => Report::new(e).wrap_err("Got error while adding voter to meeting"),
+        })?;
+        Ok(())
+    }
+}
+span_message_async_impl!(AddVoter, DbExecutor);
+
+/// Takes the user off the voter roll of the meeting, votes already cast are kept
+#[derive(Message, Clone, Debug)]
+#[rtype(result = "Result<(), Report>")]
+pub struct RemoveVoter(pub MeetingId, pub UserId);
+
+#[async_trait::async_trait]
+impl AsyncSpanHandler<RemoveVoter> for DbExecutor {
+    #[instrument]
+    async fn handle(msg: RemoveVoter) -> Result<(), Report> {
+        let RemoveVoter(meeting_id, user_id) = msg;
+        debug!("Removing voter from meeting");
+        let pool = with_ctx(|a: &mut DbExecutor, _| a.pool());
+        get_meeting(&pool, meeting_id.clone()).await?;
+        sqlx::query!(
+            "DELETE FROM meeting_voters WHERE meeting_id = $1 AND user_id = $2",
+            meeting_id.0,
+            user_id.0
+        )
+        .execute(&pool)
+        .await
+        .wrap_err("Got error while removing voter from meeting")?;
+        Ok(())
+    }
+}
+span_message_async_impl!(RemoveVoter, DbExecutor);
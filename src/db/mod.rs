@@ -1,5 +1,9 @@
 pub mod alternative;
+pub mod audit;
+pub mod delegation;
 pub mod issue;
+pub mod meeting;
+pub mod result;
 pub mod session;
 pub mod user;
 pub mod vote;
@@ -38,7 +42,7 @@ pub async fn new_pool(database_url: &str) -> Result<PgPool, sqlx::Error> {
 
 pub async fn new_pool_with(connect_options: PgConnectOptions) -> Result<PgPool, sqlx::Error> {
     PgPoolOptions::new()
-        .max_connections(5 as u32)
+        .max_connections(5_u32)
         .connect_with(connect_options)
         .await
 }
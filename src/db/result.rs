@@ -0,0 +1,115 @@
+use super::{alternative::AlternativeId, issue::IssueId, DbExecutor};
+use crate::{span::AsyncSpanHandler, span_message_async_impl};
+use actix::prelude::*;
+use actix_interop::with_ctx;
+use color_eyre::eyre::{Report, WrapErr};
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use tracing::{debug, instrument};
+
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct AlternativeCount {
+    pub alternative_id: AlternativeId,
+    /// Number of voters
+    pub count: i64,
+    /// Summed vote weight of the voters, which decides the result
+    #[serde(default)]
+    pub weight: i64,
+}
+
+/// Tally of a finished issue. Stored as JSON so it can't change after the issue finished.
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct InternalResult {
+    pub issue_id: IssueId,
+    /// Counts in the order of the issue's alternatives
+    pub counts: Vec<AlternativeCount>,
+    pub abstentions: i64,
+    /// Number of votes including abstentions
+    pub vote_count: i64,
+    pub max_voters: i32,
+    #[serde(default)]
+    pub abstention_weight: i64,
+    /// Summed weight of all votes including abstentions
+    #[serde(default)]
+    pub total_weight: i64,
+    /// Quorum is measured against the max weight if the issue has one
+    #[serde(default)]
+    pub max_weight: Option<i32>,
+    /// More than one winner means a tie. Empty if nobody voted for an alternative.
+    pub winners: Vec<AlternativeId>,
+    pub outcome: InternalOutcome,
+    /// Instant runoff rounds of ranked issues, empty for other voting methods
+    #[serde(default)]
+    pub rounds: Vec<InternalRound>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct InternalRound {
+    pub counts: Vec<AlternativeCount>,
+    /// Ballots without any remaining alternative
+    pub exhausted: i64,
+    #[serde(default)]
+    pub exhausted_weight: i64,
+    /// Alternatives dropped after this round
+    pub eliminated: Vec<AlternativeId>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InternalOutcome {
+    /// The leading alternative reached the required majority
+    Passed,
+    Failed,
+    /// Too few voters took part for the issue to be decided
+    NoQuorum,
+}
+
+#[derive(Message, Clone, Debug)]
+#[rtype(result = "Result<(), Report>")]
+pub struct SaveResult(pub InternalResult);
+
+#[async_trait::async_trait]
+impl AsyncSpanHandler<SaveResult> for DbExecutor {
+    #[instrument]
+    async fn handle(msg: SaveResult) -> Result<(), Report> {
+        let SaveResult(result) = msg;
+        debug!("Saving result for issue");
+        let pool = with_ctx(|a: &mut DbExecutor, _| a.pool());
+        sqlx::query!(
+            r#"
+            INSERT INTO issue_results (issue_id, result) VALUES ($1, $2)
+            ON CONFLICT (issue_id) DO UPDATE SET result = EXCLUDED.result, created_at = now()
+            "#,
+            result.issue_id.0,
+            Json(&result) as _
+        )
+        .execute(&pool)
+        .await
+        .wrap_err("Got error while saving result")?;
+        Ok(())
+    }
+}
+span_message_async_impl!(SaveResult, DbExecutor);
+
+#[derive(Message, Clone, Debug)]
+#[rtype(result = "Result<Option<InternalResult>, Report>")]
+pub struct ResultForIssue(pub IssueId);
+
+#[async_trait::async_trait]
+impl AsyncSpanHandler<ResultForIssue> for DbExecutor {
+    #[instrument]
+    async fn handle(msg: ResultForIssue) -> Result<Option<InternalResult>, Report> {
+        let ResultForIssue(issue_id) = msg;
+        debug!("Retrieving result for issue");
+        let pool = with_ctx(|a: &mut DbExecutor, _| a.pool());
+        let row = sqlx::query!(
+            r#"SELECT result as "result: Json<InternalResult>" FROM issue_results WHERE issue_id = $1"#,
+            issue_id.0
+        )
+        .fetch_optional(&pool)
+        .await
+        .wrap_err("Got error while retrieving result")?;
+        Ok(row.map(|row| row.result.0))
+    }
+}
+span_message_async_impl!(ResultForIssue, DbExecutor);
@@ -4,7 +4,7 @@ use actix::prelude::*;
 use actix_interop::with_ctx;
 use color_eyre::eyre::Report;
 use serde::{Deserialize, Serialize};
-use sqlx::types::Uuid;
+use sqlx::{types::Uuid, Done};
 use tracing::debug;
 
 #[derive(Clone, Hash, PartialEq, Eq, Debug, Deserialize, Serialize, sqlx::Type)]
@@ -33,6 +33,10 @@ pub struct InternalSession {
     pub user_id: UserId,
 }
 
+/// Sessions expire after a week without being used
+const SESSION_LIFETIME_SECONDS: f64 = 7.0 * 24.0 * 60.0 * 60.0;
+
+/// Looks up a session that has not expired yet and extends its lifetime
 #[derive(Message, Clone)]
 #[rtype(result = "Result<Option<InternalSession>, Report>")]
 pub struct SessionById(pub SessionId);
@@ -45,8 +49,14 @@ impl AsyncSpanHandler<SessionById> for DbExecutor {
         let pool = with_ctx(|a: &mut DbExecutor, _| a.pool());
         let user = sqlx::query_as!(
             InternalSession,
-            r#"SELECT id as "id: _", user_id as "user_id: _" FROM sessions WHERE id = $1"#,
-            session_id.0
+            r#"
+                UPDATE sessions
+                SET last_seen = now(), expires_at = now() + $2 * interval '1 second'
+                WHERE id = $1 AND expires_at > now()
+                RETURNING id as "id: _", user_id as "user_id: _"
+                "#,
+            session_id.0,
+            SESSION_LIFETIME_SECONDS
         )
         .fetch_optional(&pool)
         .await?;
@@ -72,10 +82,12 @@ impl AsyncSpanHandler<SaveSession> for DbExecutor {
         let user = sqlx::query_as!(
             InternalSession,
             r#"
-                INSERT INTO sessions (user_id) VALUES($1)
+                INSERT INTO sessions (user_id, expires_at)
+                VALUES($1, now() + $2 * interval '1 second')
                 RETURNING id as "id: _", user_id as "user_id: _"
                 "#,
-            user_id.0
+            user_id.0,
+            SESSION_LIFETIME_SECONDS
         )
         .fetch_one(&pool)
         .await?;
@@ -84,3 +96,45 @@ impl AsyncSpanHandler<SaveSession> for DbExecutor {
     }
 }
 span_message_async_impl!(SaveSession, DbExecutor);
+
+#[derive(Message, Clone)]
+#[rtype(result = "Result<(), Report>")]
+pub struct DeleteSession(pub SessionId);
+
+#[async_trait::async_trait]
+impl AsyncSpanHandler<DeleteSession> for DbExecutor {
+    async fn handle(msg: DeleteSession) -> Result<(), Report> {
+        let DeleteSession(session_id) = msg;
+        debug!(id = session_id.as_string().as_str(), "Delete session");
+        let pool = with_ctx(|a: &mut DbExecutor, _| a.pool());
+        sqlx::query!("DELETE FROM sessions WHERE id = $1", session_id.0)
+            .execute(&pool)
+            .await?;
+
+        Ok(())
+    }
+}
+span_message_async_impl!(DeleteSession, DbExecutor);
+
+#[derive(Message, Clone)]
+#[rtype(result = "Result<u64, Report>")]
+pub struct DeleteUserSessions(pub UserId);
+
+#[async_trait::async_trait]
+impl AsyncSpanHandler<DeleteUserSessions> for DbExecutor {
+    async fn handle(msg: DeleteUserSessions) -> Result<u64, Report> {
+        let DeleteUserSessions(user_id) = msg;
+        debug!(
+            user_id = user_id.as_string().as_str(),
+            "Delete all sessions for user"
+        );
+        let pool = with_ctx(|a: &mut DbExecutor, _| a.pool());
+        let deleted = sqlx::query!("DELETE FROM sessions WHERE user_id = $1", user_id.0)
+            .execute(&pool)
+            .await?
+            .rows_affected();
+
+        Ok(deleted)
+    }
+}
+span_message_async_impl!(DeleteUserSessions, DbExecutor);
@@ -3,9 +3,9 @@ use crate::{span::AsyncSpanHandler, span_message_async_impl};
 use actix::prelude::*;
 use actix_interop::with_ctx;
 use color_eyre::eyre::Report;
-use color_eyre::eyre::WrapErr;
 use serde::{Deserialize, Serialize};
-use sqlx::{types::Uuid, Executor, Postgres};
+use sqlx::{types::Uuid, Done, Executor, Postgres};
+use std::fmt;
 use tracing::debug;
 
 #[derive(Clone, Hash, PartialEq, Eq, Debug, Deserialize, Serialize, sqlx::Type)]
@@ -28,10 +28,21 @@ impl Default for UserId {
     }
 }
 
+#[derive(Clone, PartialEq, Eq, Debug, sqlx::Type)]
+#[sqlx(rename = "text")]
+#[sqlx(rename_all = "snake_case")]
+pub enum InternalUserRole {
+    Admin,
+    Chair,
+    Voter,
+    Observer,
+}
+
 #[derive(Clone, Debug)]
 pub struct InternalUser {
     pub id: UserId,
     pub username: String,
+    pub role: InternalUserRole,
 }
 
 // Find user
@@ -48,7 +59,7 @@ impl AsyncSpanHandler<UserByUsername> for DbExecutor {
         let username = msg.0;
         let user = sqlx::query_as!(
             InternalUser,
-            r#"SELECT id as "id: _", username FROM users WHERE username = $1"#,
+            r#"SELECT id as "id: _", username, role as "role: _" FROM users WHERE lower(username) = lower($1)"#,
             username
         )
         .fetch_optional(&pool)
@@ -72,7 +83,7 @@ impl AsyncSpanHandler<UserById> for DbExecutor {
         let uuid = user_id.0;
         let user = sqlx::query_as!(
             InternalUser,
-            r#"SELECT id as "id: _", username FROM users WHERE id = $1"#,
+            r#"SELECT id as "id: _", username, role as "role: _" FROM users WHERE id = $1"#,
             uuid
         )
         .fetch_optional(&pool)
@@ -83,9 +94,158 @@ impl AsyncSpanHandler<UserById> for DbExecutor {
 }
 span_message_async_impl!(UserById, DbExecutor);
 
+#[derive(Clone, Debug)]
+pub struct InternalUserCredentials {
+    pub id: UserId,
+    pub username: String,
+    pub role: InternalUserRole,
+    pub password_hash: Option<String>,
+    /// Too many failed login attempts have been made recently
+    pub locked: bool,
+}
+
+impl InternalUserCredentials {
+    pub fn into_user(self) -> InternalUser {
+        InternalUser {
+            id: self.id,
+            username: self.username,
+            role: self.role,
+        }
+    }
+}
+
+#[derive(Message, Clone)]
+#[rtype(result = "Result<Option<InternalUserCredentials>, Report>")]
+pub struct UserCredentialsByUsername(pub String);
+
+#[async_trait::async_trait]
+impl AsyncSpanHandler<UserCredentialsByUsername> for DbExecutor {
+    async fn handle(
+        msg: UserCredentialsByUsername,
+    ) -> Result<Option<InternalUserCredentials>, Report> {
+        debug!("Retrieving user credentials");
+        let pool = with_ctx(|a: &mut DbExecutor, _| a.pool());
+        let username = msg.0;
+        let credentials = sqlx::query_as!(
+            InternalUserCredentials,
+            r#"
+            SELECT
+                id as "id: _",
+                username,
+                role as "role: _",
+                password_hash,
+                COALESCE(locked_until > now(), false) as "locked!"
+            FROM users WHERE lower(username) = lower($1)
+            "#,
+            username
+        )
+        .fetch_optional(&pool)
+        .await?;
+
+        Ok(credentials)
+    }
+}
+span_message_async_impl!(UserCredentialsByUsername, DbExecutor);
+
+/// Number of failed logins in a row before the user is locked out
+const MAX_FAILED_LOGINS: i32 = 5;
+const LOCKOUT_SECONDS: f64 = 5.0 * 60.0;
+
+#[derive(Message, Clone)]
+#[rtype(result = "Result<(), Report>")]
+pub struct RecordLoginAttempt {
+    pub user_id: UserId,
+    pub success: bool,
+}
+
+#[async_trait::async_trait]
+impl AsyncSpanHandler<RecordLoginAttempt> for DbExecutor {
+    async fn handle(msg: RecordLoginAttempt) -> Result<(), Report> {
+        debug!(success = msg.success, "Recording login attempt");
+        let pool = with_ctx(|a: &mut DbExecutor, _| a.pool());
+        if msg.success {
+            sqlx::query!(
+                "UPDATE users SET failed_login_attempts = 0, locked_until = NULL WHERE id = $1",
+                msg.user_id.0
+            )
+            .execute(&pool)
+            .await?;
+        } else {
+            // Counter restarts once the user has been locked out
+            sqlx::query!(
+                r#"
+                UPDATE users SET
+                    failed_login_attempts = CASE
+                        WHEN failed_login_attempts + 1 >= $2 THEN 0
+                        ELSE failed_login_attempts + 1
+                    END,
+                    locked_until = CASE
+                        WHEN failed_login_attempts + 1 >= $2 THEN now() + $3 * interval '1 second'
+                        ELSE locked_until
+                    END
+                WHERE id = $1
+                "#,
+                msg.user_id.0,
+                MAX_FAILED_LOGINS,
+                LOCKOUT_SECONDS
+            )
+            .execute(&pool)
+            .await?;
+        }
+        Ok(())
+    }
+}
+span_message_async_impl!(RecordLoginAttempt, DbExecutor);
+
+const USERNAME_MIN_LENGTH: usize = 3;
+const USERNAME_MAX_LENGTH: usize = 40;
+
+/// Postgres error code for unique constraint violations
+const UNIQUE_VIOLATION: &str = "23505";
+
+#[derive(Debug)]
+pub enum RegistrationError {
+    InvalidUsername(&'static str),
+    InvalidPassword(&'static str),
+    UsernameTaken(String),
+}
+
+impl fmt::Display for RegistrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistrationError::InvalidUsername(reason) => write!(f, "Invalid username: {}", reason),
+            RegistrationError::InvalidPassword(reason) => write!(f, "Invalid password: {}", reason),
+            RegistrationError::UsernameTaken(username) => {
+                write!(f, "Username {} is already taken", username)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RegistrationError {}
+
+fn validate_username(username: &str) -> Result<(), RegistrationError> {
+    let length = username.chars().count();
+    if !(USERNAME_MIN_LENGTH..=USERNAME_MAX_LENGTH).contains(&length) {
+        return Err(RegistrationError::InvalidUsername(
+            "must be between 3 and 40 characters long",
+        ));
+    }
+    if !username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+    {
+        return Err(RegistrationError::InvalidUsername(
+            "may only contain letters, digits, '_', '-' and '.'",
+        ));
+    }
+    Ok(())
+}
+
 #[derive(Clone, Debug)]
 pub struct NewInternalUser {
     pub username: String,
+    pub password_hash: String,
 }
 
 #[derive(Message, Clone)]
@@ -96,20 +256,29 @@ async fn insert_new_user(
     executor: impl Executor<'_, Database = Postgres>,
     data: NewInternalUser,
 ) -> Result<InternalUser, Report> {
+    validate_username(&data.username)?;
+    let username = data.username.clone();
     sqlx::query_as!(
         InternalUser,
         r#"
-        INSERT INTO users ( username )
-        VALUES ( $1 )
+        INSERT INTO users ( username, password_hash )
+        VALUES ( $1, $2 )
         RETURNING
             id as "id: _",
-            username as "username: _"
+            username as "username: _",
+            role as "role: _"
         "#,
         data.username,
+        data.password_hash,
     )
     .fetch_one(executor)
     .await
-    .wrap_err("Got error while adding new issue to db")
+    .map_err(|e| match e {
+        sqlx::Error::Database(err) if err.code().as_deref() == Some(UNIQUE_VIOLATION) => {
+            RegistrationError::UsernameTaken(username).into()
+        }
+        e => Report::new(e).wrap_err("Got error while adding new user to db"),
+    })
 }
 
 #[async_trait::async_trait]
@@ -122,3 +291,107 @@ impl AsyncSpanHandler<NewUser> for DbExecutor {
     }
 }
 span_message_async_impl!(NewUser, DbExecutor);
+
+// Vote weight
+
+#[derive(Debug)]
+pub enum VoteWeightError {
+    NotPositive(i32),
+    UserNotFound(UserId),
+}
+
+impl fmt::Display for VoteWeightError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VoteWeightError::NotPositive(weight) => {
+                write!(f, "Vote weight must be positive, got {}", weight)
+            }
+            VoteWeightError::UserNotFound(user_id) => {
+                write!(f, "User {} not found", user_id.as_string())
+            }
+        }
+    }
+}
+
+impl std::error::Error for VoteWeightError {}
+
+/// Sets how many times the user's future votes count, such as the number of shares they hold.
+/// Votes already cast keep the weight they were cast with.
+#[derive(Message, Clone, Debug)]
+#[rtype(result = "Result<(), Report>")]
+pub struct SetVoteWeight(pub UserId, pub i32);
+
+#[async_trait::async_trait]
+impl AsyncSpanHandler<SetVoteWeight> for DbExecutor {
+    async fn handle(msg: SetVoteWeight) -> Result<(), Report> {
+        debug!("Setting vote weight of user");
+        let SetVoteWeight(user_id, weight) = msg;
+        if weight < 1 {
+            return Err(VoteWeightError::NotPositive(weight).into());
+        }
+        let pool = with_ctx(|a: &mut DbExecutor, _| a.pool());
+        let updated = sqlx::query!(
+            "UPDATE users SET vote_weight = $2 WHERE id = $1",
+            user_id.0,
+            weight
+        )
+        .execute(&pool)
+        .await?
+        .rows_affected();
+        if updated == 0 {
+            return Err(VoteWeightError::UserNotFound(user_id).into());
+        }
+        Ok(())
+    }
+}
+span_message_async_impl!(SetVoteWeight, DbExecutor);
+
+// Passwords
+
+#[derive(Debug)]
+pub enum PasswordError {
+    UserNotFound(UserId),
+}
+
+impl fmt::Display for PasswordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PasswordError::UserNotFound(user_id) => {
+                write!(f, "User {} not found", user_id.as_string())
+            }
+        }
+    }
+}
+
+impl std::error::Error for PasswordError {}
+
+/// Replaces the password hash of the user, such as one added without a password.
+/// Also lifts a lockout, so the user can log in with the new password right away.
+#[derive(Message, Clone)]
+#[rtype(result = "Result<(), Report>")]
+pub struct SetPasswordHash(pub UserId, pub String);
+
+#[async_trait::async_trait]
+impl AsyncSpanHandler<SetPasswordHash> for DbExecutor {
+    async fn handle(msg: SetPasswordHash) -> Result<(), Report> {
+        debug!("Setting password of user");
+        let SetPasswordHash(user_id, password_hash) = msg;
+        let pool = with_ctx(|a: &mut DbExecutor, _| a.pool());
+        let updated = sqlx::query!(
+            r#"
+            UPDATE users SET password_hash = $2, failed_login_attempts = 0, locked_until = NULL
+            WHERE id = $1
+            "#,
+            user_id.0,
+            password_hash
+        )
+        .execute(&pool)
+        .await?
+        .rows_affected();
+        if updated == 0 {
+            return Err(PasswordError::UserNotFound(user_id).into());
+        }
+        Ok(())
+    }
+}
+span_message_async_impl!(SetPasswordHash, DbExecutor);
@@ -1,10 +1,19 @@
-use super::{alternative::AlternativeId, issue::IssueId, user::UserId, DbExecutor};
+use super::{
+    alternative::AlternativeId,
+    audit::{insert_audit, InternalAuditAction, NewAuditEntry},
+    delegation::has_delegation,
+    issue::{InternalIssueState, InternalVotingMethod, IssueError, IssueId},
+    meeting::{on_voter_roll, MeetingId},
+    user::UserId,
+    DbExecutor,
+};
 use crate::span::AsyncSpanHandler;
 use actix::prelude::*;
 use actix_interop::with_ctx;
-use color_eyre::eyre::{eyre, Report, WrapErr};
+use color_eyre::eyre::{Report, WrapErr};
 use serde::{Deserialize, Serialize};
-use sqlx::{types::Uuid, Executor, Postgres};
+use sqlx::{types::Uuid, Done, Executor, Postgres};
+use std::fmt;
 use tracing::{debug, instrument};
 
 #[derive(Clone, Hash, PartialEq, Eq, Debug, Deserialize, Serialize, sqlx::Type)]
@@ -25,15 +34,139 @@ impl Default for VoteId {
 
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct InternalVote {
-    pub id: VoteId,
+    /// Secret ballots have no id until they are published when their issue finishes
+    pub id: Option<VoteId>,
+    /// No alternative means the user abstained
+    pub alternative_id: Option<AlternativeId>,
+    pub issue_id: IssueId,
+    /// Votes on secret issues are not linked to the voter
+    pub user_id: Option<UserId>,
+    /// Proxy who cast the vote for the user
+    pub cast_by: Option<UserId>,
+    /// Vote weight of the user when the vote was cast,
+    /// secret ballots leave it out and add it to their `InternalSecretWeight` once published
+    pub weight: Option<i32>,
+}
+
+/// Alternatives the user chose, the form depends on the voting method of the issue
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum InternalBallot {
+    /// A single alternative, no alternative means the user abstained
+    Single(Option<AlternativeId>),
+    /// Alternatives in order of preference
+    Ranked(Vec<AlternativeId>),
+    /// Alternatives the user approves of, in no particular order
+    Approval(Vec<AlternativeId>),
+}
+
+impl InternalBallot {
+    /// The alternative stored on the vote itself
+    fn first_choice(&self) -> Option<AlternativeId> {
+        match self {
+            InternalBallot::Single(alternative_id) => alternative_id.clone(),
+            InternalBallot::Ranked(choices) | InternalBallot::Approval(choices) => {
+                choices.first().cloned()
+            }
+        }
+    }
+
+    fn choices(&self) -> &[AlternativeId] {
+        match self {
+            InternalBallot::Single(Some(alternative_id)) => std::slice::from_ref(alternative_id),
+            InternalBallot::Single(None) => &[],
+            InternalBallot::Ranked(choices) | InternalBallot::Approval(choices) => choices,
+        }
+    }
+}
+
+/// Summed weight of the secret ballots of an issue which made the same choices
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct InternalSecretWeight {
+    /// Alternatives in the order they count, empty for abstentions
+    pub choices: Vec<AlternativeId>,
+    pub ballots: i32,
+    pub weight: i64,
+}
+
+/// One entry of a ranked or approval ballot, ordered by position when loaded
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct InternalChoice {
+    pub vote_id: VoteId,
     pub alternative_id: AlternativeId,
+}
+
+#[derive(Debug)]
+pub enum VoteError {
+    IssueNotInProgress(InternalIssueState),
+    AlreadyVoted,
+    NotVoted,
+    ChangeNotAllowed,
+    NotDelegated,
+    /// The user isn't on the voter roll of the issue's meeting
+    NotEligible,
+    MaxVotersReached(i32),
+    MaxWeightReached(i32),
+    InvalidBallot(&'static str),
+}
+
+impl fmt::Display for VoteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VoteError::IssueNotInProgress(state) => {
+                write!(f, "Can't vote on issue while it is {:?}", state)
+            }
+            VoteError::AlreadyVoted => write!(f, "User has already voted"),
+            VoteError::NotVoted => write!(f, "User has not voted"),
+            VoteError::ChangeNotAllowed => write!(f, "Votes can't be changed on this issue"),
+            VoteError::NotDelegated => write!(f, "User has not granted a proxy to the voter"),
+            VoteError::NotEligible => write!(f, "User is not eligible to vote on this issue"),
+            VoteError::MaxVotersReached(max_voters) => {
+                write!(f, "All {} voters have already voted", max_voters)
+            }
+            VoteError::MaxWeightReached(max_weight) => {
+                write!(f, "Vote would exceed the total weight of {}", max_weight)
+            }
+            VoteError::InvalidBallot(reason) => write!(f, "Invalid ballot: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for VoteError {}
+
+/// Vote count of an issue after a change, and whether the votes themselves may be shown
+#[derive(Clone, Debug)]
+pub struct VoteTally {
     pub issue_id: IssueId,
-    pub user_id: UserId,
+    pub meeting_id: Option<MeetingId>,
+    pub vote_count: i64,
+    pub max_voters: i32,
+    /// Summed weight of all votes
+    pub total_weight: i64,
+    pub max_weight: Option<i32>,
+    pub show_distribution: bool,
 }
 
+#[derive(Clone, Debug)]
+pub struct AddedVote {
+    pub vote: InternalVote,
+    pub tally: VoteTally,
+    /// The user had already voted and their vote was changed
+    pub replaced: bool,
+    /// The vote was the last one allowed and the issue has been finished
+    pub issue_finished: bool,
+}
+
+/// Vote of the user, or of the principal which the user casts as proxy
 #[derive(Message, Clone, Debug)]
-#[rtype(result = "Result<InternalVote, Report>")]
-pub struct AddVote(pub UserId, pub IssueId, pub AlternativeId);
+#[rtype(result = "Result<AddedVote, Report>")]
+pub struct AddVote {
+    pub voter_id: UserId,
+    pub issue_id: IssueId,
+    pub ballot: InternalBallot,
+    pub on_behalf_of: Option<UserId>,
+    /// Admin casting the vote for the voter in demo mode, audited along with the vote
+    pub demo_admin: Option<UserId>,
+}
 
 async fn get_vote_for_user(
     executor: impl Executor<'_, Database = Postgres>,
@@ -44,10 +177,12 @@ async fn get_vote_for_user(
         InternalVote,
         r#"
         SELECT
-            id as "id: _",
+            id as "id?: _",
             alternative_id as "alternative_id: _",
             issue_id as "issue_id: _",
-            user_id as "user_id: _"
+            user_id as "user_id: _",
+            cast_by as "cast_by: _",
+            weight
         FROM votes
         WHERE user_id= $1 AND issue_id = $2
         "#,
@@ -59,6 +194,7 @@ async fn get_vote_for_user(
     .wrap_err("Got error while retrieving vote for user")
 }
 
+/// Secret ballots which are still queued are included without an id
 async fn get_votes_for_issue(
     executor: impl Executor<'_, Database = Postgres>,
     issue_id: IssueId,
@@ -67,12 +203,18 @@ async fn get_votes_for_issue(
         InternalVote,
         r#"
         SELECT
-            id as "id: _",
+            id as "id?: _",
             alternative_id as "alternative_id: _",
-            issue_id as "issue_id: _",
-            user_id as "user_id: _"
+            issue_id as "issue_id!: _",
+            user_id as "user_id: _",
+            cast_by as "cast_by: _",
+            weight
         FROM votes
         WHERE issue_id = $1
+        UNION ALL
+        SELECT NULL, choices[1], issue_id, NULL, NULL, NULL
+        FROM secret_ballots
+        WHERE issue_id = $1
         "#,
         issue_id.0,
     )
@@ -81,46 +223,490 @@ async fn get_votes_for_issue(
     .wrap_err("Got error while retrieving votes for issue")
 }
 
+/// Number of votes on the issue and their summed weight
+async fn count_votes_for_issue(
+    executor: impl Executor<'_, Database = Postgres>,
+    issue_id: IssueId,
+) -> Result<(i64, i64), Report> {
+    let row = sqlx::query!(
+        r#"
+        SELECT COUNT(*) + (SELECT COUNT(*) FROM secret_ballots WHERE issue_id = $1) as "count!",
+            COALESCE(SUM(weight), 0) + COALESCE((SELECT SUM(weight)::bigint FROM secret_vote_weights WHERE issue_id = $1), 0)
+                + COALESCE((SELECT SUM(weight) FROM secret_ballots WHERE issue_id = $1), 0) as "weight!"
+        FROM votes WHERE issue_id = $1
+        "#,
+        issue_id.0,
+    )
+    .fetch_one(executor)
+    .await
+    .wrap_err("Got error while counting votes for issue")?;
+    Ok((row.count, row.weight))
+}
+
+async fn get_vote_weight(
+    executor: impl Executor<'_, Database = Postgres>,
+    user_id: &UserId,
+) -> Result<i32, Report> {
+    let row = sqlx::query!("SELECT vote_weight FROM users WHERE id = $1", user_id.0)
+        .fetch_one(executor)
+        .await
+        .wrap_err("Got error while retrieving vote weight of user")?;
+    Ok(row.vote_weight)
+}
+
 async fn insert_vote(
     executor: impl Executor<'_, Database = Postgres>,
-    alternative_id: AlternativeId,
+    alternative_id: Option<AlternativeId>,
     issue_id: IssueId,
     user_id: UserId,
+    cast_by: Option<UserId>,
+    weight: i32,
 ) -> Result<InternalVote, Report> {
     sqlx::query_as!(
         InternalVote,
         r#"
-        INSERT INTO votes (alternative_id, issue_id, user_id) VALUES($1, $2, $3)
-        RETURNING id as "id: _", alternative_id as "alternative_id: _", issue_id as "issue_id: _", user_id as "user_id: _"
+        INSERT INTO votes (alternative_id, issue_id, user_id, cast_by, weight) VALUES($1, $2, $3, $4, $5)
+        RETURNING id as "id?: _", alternative_id as "alternative_id: _", issue_id as "issue_id: _", user_id as "user_id: _", cast_by as "cast_by: _", weight
         "#,
-        alternative_id.0,
+        alternative_id.map(|id| id.0),
         issue_id.0,
         user_id.0,
+        cast_by.map(|id| id.0),
+        weight,
     )
     .fetch_one(executor)
     .await
     .wrap_err("Got error while adding vote to DB")
 }
 
+/// Voting rules of an issue which is locked while votes are changed
+struct LockedIssue {
+    /// Meeting issues without a limit of their own are open to the current voter roll
+    max_voters: i32,
+    allow_vote_change: bool,
+    secret: bool,
+    show_distribution: bool,
+    voting_method: InternalVotingMethod,
+    min_choices: i32,
+    max_choices: Option<i32>,
+    max_weight: Option<i32>,
+    meeting_id: Option<MeetingId>,
+}
+
+/// Locks the issue until the transaction ends. Fails unless the issue is in progress.
+async fn lock_issue_in_progress(
+    executor: impl Executor<'_, Database = Postgres>,
+    issue_id: IssueId,
+) -> Result<LockedIssue, Report> {
+    let issue = sqlx::query!(
+        r#"
+        SELECT
+            state as "state: InternalIssueState",
+            COALESCE(max_voters, (SELECT COUNT(*) FROM meeting_voters WHERE meeting_voters.meeting_id = issues.meeting_id)::integer) as "max_voters!",
+            allow_vote_change,
+            secret,
+            show_distribution,
+            voting_method as "voting_method: InternalVotingMethod",
+            min_choices,
+            max_choices,
+            max_weight,
+            meeting_id as "meeting_id: MeetingId"
+        FROM issues WHERE id = $1 FOR UPDATE
+        "#,
+        issue_id.0
+    )
+    .fetch_optional(executor)
+    .await?
+    .ok_or_else(|| IssueError::NotFound(issue_id.clone()))?;
+    if issue.state != InternalIssueState::InProgress {
+        return Err(VoteError::IssueNotInProgress(issue.state).into());
+    }
+    Ok(LockedIssue {
+        max_voters: issue.max_voters,
+        allow_vote_change: issue.allow_vote_change,
+        secret: issue.secret,
+        show_distribution: issue.show_distribution,
+        voting_method: issue.voting_method,
+        min_choices: issue.min_choices,
+        max_choices: issue.max_choices,
+        max_weight: issue.max_weight,
+        meeting_id: issue.meeting_id,
+    })
+}
+
+/// Checks that the ballot fits the voting method and only contains alternatives of the issue
+async fn validate_ballot(
+    executor: impl Executor<'_, Database = Postgres>,
+    issue_id: IssueId,
+    issue: &LockedIssue,
+    ballot: &InternalBallot,
+) -> Result<(), Report> {
+    match (&issue.voting_method, ballot) {
+        // Abstaining is possible regardless of the voting method
+        (_, InternalBallot::Single(None)) => {}
+        (InternalVotingMethod::Plurality, InternalBallot::Single(Some(_))) => {}
+        (InternalVotingMethod::Ranked, InternalBallot::Ranked(ranking)) => {
+            if ranking.is_empty() {
+                return Err(VoteError::InvalidBallot("ranking is empty").into());
+            }
+        }
+        (InternalVotingMethod::Approval, InternalBallot::Approval(approvals)) => {
+            let selected = approvals.len() as i64;
+            if selected < i64::from(issue.min_choices) {
+                return Err(VoteError::InvalidBallot("too few alternatives selected").into());
+            }
+            if issue
+                .max_choices
+                .map_or(false, |max| selected > i64::from(max))
+            {
+                return Err(VoteError::InvalidBallot("too many alternatives selected").into());
+            }
+        }
+        (InternalVotingMethod::Plurality, _) => {
+            return Err(VoteError::InvalidBallot("issue requires a single alternative").into());
+        }
+        (InternalVotingMethod::Ranked, _) => {
+            return Err(VoteError::InvalidBallot("issue requires a ranked ballot").into());
+        }
+        (InternalVotingMethod::Approval, _) => {
+            return Err(VoteError::InvalidBallot("issue requires a list of alternatives").into());
+        }
+    }
+    let choices = ballot.choices();
+    if choices
+        .iter()
+        .enumerate()
+        .any(|(i, alternative_id)| choices[..i].contains(alternative_id))
+    {
+        return Err(VoteError::InvalidBallot("alternative is selected twice").into());
+    }
+
+    let alternatives = sqlx::query!(
+        r#"SELECT id as "id: AlternativeId" FROM alternatives WHERE issue_id = $1"#,
+        issue_id.0
+    )
+    .fetch_all(executor)
+    .await
+    .wrap_err("Got error while retrieving alternatives for ballot")?;
+    if choices.iter().any(|choice| {
+        !alternatives
+            .iter()
+            .any(|alternative| &alternative.id == choice)
+    }) {
+        return Err(VoteError::InvalidBallot("alternative doesn't belong to the issue").into());
+    }
+    Ok(())
+}
+
+/// Replaces the ordered choices stored for a vote
+async fn replace_choices(
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    vote_id: &VoteId,
+    ballot: &InternalBallot,
+) -> Result<(), Report> {
+    sqlx::query!("DELETE FROM vote_choices WHERE vote_id = $1", vote_id.0)
+        .execute(&mut *tx)
+        .await
+        .wrap_err("Got error while removing choices of vote")?;
+    if let InternalBallot::Ranked(choices) | InternalBallot::Approval(choices) = ballot {
+        for (position, alternative_id) in choices.iter().enumerate() {
+            sqlx::query!(
+                "INSERT INTO vote_choices (vote_id, position, alternative_id) VALUES ($1, $2, $3)",
+                vote_id.0,
+                position as i32,
+                alternative_id.0
+            )
+            .execute(&mut *tx)
+            .await
+            .wrap_err("Got error while adding choice of vote")?;
+        }
+    }
+    Ok(())
+}
+
+/// Queues a secret ballot with the weight of its voter until its issue finishes
+async fn queue_secret_ballot(
+    executor: impl Executor<'_, Database = Postgres>,
+    issue_id: &IssueId,
+    ballot: &InternalBallot,
+    weight: i32,
+) -> Result<(), Report> {
+    let mut choices: Vec<Uuid> = ballot.choices().iter().map(|id| id.0).collect();
+    // Approvals count the same in any order
+    if let InternalBallot::Approval(_) = ballot {
+        choices.sort_unstable();
+    }
+    sqlx::query!(
+        "INSERT INTO secret_ballots (issue_id, choices, weight) VALUES ($1, $2, $3)",
+        issue_id.0,
+        &choices,
+        weight,
+    )
+    .execute(executor)
+    .await
+    .wrap_err("Got error while queueing secret ballot")?;
+    Ok(())
+}
+
+/// Publishes the queued secret ballots of a finished issue as anonymous votes, ordered by their choices,
+/// and adds their weight to the ballots with the same choices.
+/// Never run this in the transaction of a vote, no vote may share a transaction with the receipt of its voter.
+pub(crate) async fn publish_secret_ballots(
+    executor: impl Executor<'_, Database = Postgres>,
+    issue_id: &IssueId,
+    voting_method: &InternalVotingMethod,
+) -> Result<(), Report> {
+    sqlx::query!(
+        r#"
+        WITH queued AS (
+            DELETE FROM secret_ballots WHERE issue_id = $1
+            RETURNING choices, weight
+        ),
+        weights AS (
+            INSERT INTO secret_vote_weights (issue_id, choices, ballots, weight)
+            SELECT $1, choices, COUNT(*)::integer, SUM(weight) FROM queued GROUP BY choices
+            ON CONFLICT (issue_id, choices) DO UPDATE
+            SET ballots = secret_vote_weights.ballots + EXCLUDED.ballots, weight = secret_vote_weights.weight + EXCLUDED.weight
+        ),
+        ballots AS (
+            SELECT uuid_generate_v4() AS id, choices FROM queued
+        ),
+        added AS (
+            INSERT INTO votes (id, issue_id, alternative_id)
+            SELECT id, $1, choices[1] FROM ballots
+            ORDER BY choices, id
+        )
+        INSERT INTO vote_choices (vote_id, position, alternative_id)
+        SELECT ballots.id, (choice.position - 1)::integer, choice.alternative_id
+        FROM ballots CROSS JOIN LATERAL unnest(ballots.choices) WITH ORDINALITY AS choice(alternative_id, position)
+        WHERE $2
+        "#,
+        issue_id.0,
+        *voting_method != InternalVotingMethod::Plurality,
+    )
+    .execute(executor)
+    .await
+    .wrap_err("Got error while publishing secret ballots")?;
+    Ok(())
+}
+
+/// Inserts the vote or changes the alternative of the user's existing vote
+async fn upsert_vote(
+    executor: impl Executor<'_, Database = Postgres>,
+    alternative_id: Option<AlternativeId>,
+    issue_id: IssueId,
+    user_id: UserId,
+    cast_by: Option<UserId>,
+    weight: i32,
+) -> Result<InternalVote, Report> {
+    sqlx::query_as!(
+        InternalVote,
+        r#"
+        INSERT INTO votes (alternative_id, issue_id, user_id, cast_by, weight) VALUES($1, $2, $3, $4, $5)
+        ON CONFLICT (user_id, issue_id) DO UPDATE
+        SET alternative_id = EXCLUDED.alternative_id, cast_by = EXCLUDED.cast_by, weight = EXCLUDED.weight
+        RETURNING id as "id?: _", alternative_id as "alternative_id: _", issue_id as "issue_id: _", user_id as "user_id: _", cast_by as "cast_by: _", weight
+        "#,
+        alternative_id.map(|id| id.0),
+        issue_id.0,
+        user_id.0,
+        cast_by.map(|id| id.0),
+        weight,
+    )
+    .fetch_one(executor)
+    .await
+    .wrap_err("Got error while upserting vote in DB")
+}
+
+/// Records that the user has voted on a secret issue.
+/// Returns false if the user already has a receipt.
+/// A proxy is recorded on the receipt so the ballot stays unlinked.
+async fn insert_ballot_receipt(
+    executor: impl Executor<'_, Database = Postgres>,
+    issue_id: IssueId,
+    user_id: UserId,
+    cast_by: Option<UserId>,
+) -> Result<bool, Report> {
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO ballot_receipts (issue_id, user_id, cast_by) VALUES($1, $2, $3)
+        ON CONFLICT DO NOTHING
+        "#,
+        issue_id.0,
+        user_id.0,
+        cast_by.map(|id| id.0),
+    )
+    .execute(executor)
+    .await
+    .wrap_err("Got error while adding ballot receipt to DB")?
+    .rows_affected();
+    Ok(inserted > 0)
+}
+
 #[async_trait::async_trait]
 impl AsyncSpanHandler<AddVote> for DbExecutor {
     #[instrument]
-    async fn handle(msg: AddVote) -> Result<InternalVote, Report> {
+    async fn handle(msg: AddVote) -> Result<AddedVote, Report> {
         debug!(vote = ?msg, "Adding vote");
-        let AddVote(user_id, issue_id, alternative_id) = msg;
+        let AddVote {
+            voter_id,
+            issue_id,
+            ballot,
+            on_behalf_of,
+            demo_admin,
+        } = msg;
 
         let pool = with_ctx(|a: &mut DbExecutor, _| a.pool());
         let mut tx = pool.begin().await?;
 
-        let user_vote = get_vote_for_user(&mut tx, user_id.clone(), issue_id.clone()).await?;
-        if user_vote.is_some() {
-            return Err(eyre!("User has already voted"));
+        // Locking the issue serializes votes so the max voters count can't be exceeded
+        // and prevents the issue from being finished while the vote is added
+        let issue = lock_issue_in_progress(&mut tx, issue_id.clone()).await?;
+        validate_ballot(&mut tx, issue_id.clone(), &issue, &ballot).await?;
+
+        // The vote belongs to the principal and records the proxy casting it
+        let (user_id, cast_by) = match on_behalf_of {
+            Some(principal_id) => {
+                if !has_delegation(
+                    &mut tx,
+                    &principal_id,
+                    &voter_id,
+                    &issue_id,
+                    issue.meeting_id.as_ref(),
+                )
+                .await?
+                {
+                    return Err(VoteError::NotDelegated.into());
+                }
+                (principal_id, Some(voter_id))
+            }
+            None => (voter_id, None),
+        };
+
+        // Issues of a meeting are restricted to its voter roll, which the principal must be on
+        if let Some(meeting_id) = &issue.meeting_id {
+            if !on_voter_roll(&mut tx, meeting_id, &user_id).await? {
+                return Err(VoteError::NotEligible.into());
+            }
+        }
+
+        // Secret ballots can't be found again, so they can't be replaced either
+        let user_vote = if issue.secret {
+            None
+        } else {
+            get_vote_for_user(&mut tx, user_id.clone(), issue_id.clone()).await?
+        };
+        let replaced = user_vote.is_some();
+        if replaced && !issue.allow_vote_change {
+            return Err(VoteError::AlreadyVoted.into());
+        }
+
+        // Changed votes don't count towards max voters again
+        let (vote_count, total_weight) = count_votes_for_issue(&mut tx, issue_id.clone()).await?;
+        if !replaced && vote_count >= i64::from(issue.max_voters) {
+            return Err(VoteError::MaxVotersReached(issue.max_voters).into());
+        }
+
+        // A changed vote replaces the weight of the old one, the user's weight may have changed since
+        let weight = get_vote_weight(&mut tx, &user_id).await?;
+        let total_weight = total_weight + i64::from(weight)
+            - user_vote.and_then(|vote| vote.weight).map_or(0, i64::from);
+        if let Some(max_weight) = issue.max_weight {
+            if total_weight > i64::from(max_weight) {
+                return Err(VoteError::MaxWeightReached(max_weight).into());
+            }
+        }
+
+        // Demo votes are only stored along with their audit entry
+        if let Some(admin_id) = demo_admin {
+            insert_audit(
+                &mut tx,
+                NewAuditEntry {
+                    actor_id: admin_id,
+                    action: InternalAuditAction::DemoVote,
+                    user_id: Some(user_id.clone()),
+                    issue_id: Some(issue_id.clone()),
+                },
+            )
+            .await?;
+        }
+
+        let alternative_id = ballot.first_choice();
+        let inserted_vote = if issue.secret {
+            if !insert_ballot_receipt(&mut tx, issue_id.clone(), user_id, cast_by).await? {
+                return Err(VoteError::AlreadyVoted.into());
+            }
+            queue_secret_ballot(&mut tx, &issue_id, &ballot, weight).await?;
+            InternalVote {
+                id: None,
+                alternative_id,
+                issue_id: issue_id.clone(),
+                user_id: None,
+                cast_by: None,
+                weight: None,
+            }
+        } else if issue.allow_vote_change {
+            upsert_vote(
+                &mut tx,
+                alternative_id,
+                issue_id.clone(),
+                user_id,
+                cast_by,
+                weight,
+            )
+            .await?
+        } else {
+            insert_vote(
+                &mut tx,
+                alternative_id,
+                issue_id.clone(),
+                user_id,
+                cast_by,
+                weight,
+            )
+            .await?
+        };
+        if let Some(vote_id) = &inserted_vote.id {
+            replace_choices(&mut tx, vote_id, &ballot).await?;
+        }
+
+        let vote_count = if replaced { vote_count } else { vote_count + 1 };
+        let issue_finished = (!replaced && vote_count >= i64::from(issue.max_voters))
+            || issue
+                .max_weight
+                .map_or(false, |max_weight| total_weight >= i64::from(max_weight));
+        if issue_finished {
+            debug!("Last vote received, finishing issue");
+            sqlx::query!(
+                "UPDATE issues SET state = $2 WHERE id = $1",
+                issue_id.0,
+                InternalIssueState::Finished as InternalIssueState
+            )
+            .execute(&mut tx)
+            .await
+            .wrap_err("Got error while finishing issue")?;
         }
-        let inserted_vote = insert_vote(&mut tx, alternative_id, issue_id, user_id).await?;
 
         tx.commit().await?;
 
-        Ok(inserted_vote)
+        if issue_finished && issue.secret {
+            publish_secret_ballots(&pool, &issue_id, &issue.voting_method).await?;
+        }
+
+        Ok(AddedVote {
+            vote: inserted_vote,
+            tally: VoteTally {
+                issue_id,
+                meeting_id: issue.meeting_id,
+                vote_count,
+                max_voters: issue.max_voters,
+                total_weight,
+                max_weight: issue.max_weight,
+                show_distribution: issue.show_distribution,
+            },
+            replaced,
+            issue_finished,
+        })
     }
 }
 crate::span_message_async_impl!(AddVote, DbExecutor);
@@ -141,3 +727,157 @@ impl AsyncSpanHandler<VotesForIssue> for DbExecutor {
     }
 }
 crate::span_message_async_impl!(VotesForIssue, DbExecutor);
+
+#[derive(Message, Clone, Debug)]
+#[rtype(result = "Result<Vec<InternalChoice>, Report>")]
+pub struct ChoicesForIssue(pub IssueId);
+
+#[async_trait::async_trait]
+impl AsyncSpanHandler<ChoicesForIssue> for DbExecutor {
+    #[instrument]
+    async fn handle(msg: ChoicesForIssue) -> Result<Vec<InternalChoice>, Report> {
+        debug!("Retrieving ranked choices for issue");
+        let ChoicesForIssue(issue_id) = msg;
+
+        let pool = with_ctx(|a: &mut DbExecutor, _| a.pool());
+        sqlx::query_as!(
+            InternalChoice,
+            r#"
+            SELECT vote_choices.vote_id as "vote_id: _", vote_choices.alternative_id as "alternative_id: _"
+            FROM vote_choices JOIN votes ON votes.id = vote_choices.vote_id
+            WHERE votes.issue_id = $1
+            ORDER BY vote_choices.vote_id, vote_choices.position
+            "#,
+            issue_id.0,
+        )
+        .fetch_all(&pool)
+        .await
+        .wrap_err("Got error while retrieving choices for issue")
+    }
+}
+crate::span_message_async_impl!(ChoicesForIssue, DbExecutor);
+
+#[derive(Message, Clone, Debug)]
+#[rtype(result = "Result<Vec<InternalSecretWeight>, Report>")]
+pub struct SecretWeightsForIssue(pub IssueId);
+
+#[async_trait::async_trait]
+impl AsyncSpanHandler<SecretWeightsForIssue> for DbExecutor {
+    #[instrument]
+    async fn handle(msg: SecretWeightsForIssue) -> Result<Vec<InternalSecretWeight>, Report> {
+        debug!("Retrieving secret ballot weights for issue");
+        let SecretWeightsForIssue(issue_id) = msg;
+
+        let pool = with_ctx(|a: &mut DbExecutor, _| a.pool());
+        let rows = sqlx::query!(
+            "SELECT choices, ballots, weight FROM secret_vote_weights WHERE issue_id = $1",
+            issue_id.0,
+        )
+        .fetch_all(&pool)
+        .await
+        .wrap_err("Got error while retrieving secret ballot weights for issue")?;
+        Ok(rows
+            .into_iter()
+            .map(|row| InternalSecretWeight {
+                choices: row.choices.into_iter().map(AlternativeId).collect(),
+                ballots: row.ballots,
+                weight: row.weight,
+            })
+            .collect())
+    }
+}
+crate::span_message_async_impl!(SecretWeightsForIssue, DbExecutor);
+
+#[derive(Message, Clone, Debug)]
+#[rtype(result = "Result<Option<InternalVote>, Report>")]
+pub struct VoteForUser(pub UserId, pub IssueId);
+
+#[async_trait::async_trait]
+impl AsyncSpanHandler<VoteForUser> for DbExecutor {
+    #[instrument]
+    async fn handle(msg: VoteForUser) -> Result<Option<InternalVote>, Report> {
+        debug!("Retrieving vote for user");
+        let VoteForUser(user_id, issue_id) = msg;
+
+        let pool = with_ctx(|a: &mut DbExecutor, _| a.pool());
+        get_vote_for_user(&pool, user_id, issue_id).await
+    }
+}
+crate::span_message_async_impl!(VoteForUser, DbExecutor);
+
+#[derive(Clone, Debug)]
+pub struct RetractedVote {
+    pub vote: InternalVote,
+    pub tally: VoteTally,
+}
+
+#[derive(Message, Clone, Debug)]
+#[rtype(result = "Result<RetractedVote, Report>")]
+pub struct RetractVote(pub UserId, pub IssueId, pub Option<UserId>);
+
+#[async_trait::async_trait]
+impl AsyncSpanHandler<RetractVote> for DbExecutor {
+    #[instrument]
+    async fn handle(msg: RetractVote) -> Result<RetractedVote, Report> {
+        debug!(vote = ?msg, "Retracting vote");
+        let RetractVote(voter_id, issue_id, on_behalf_of) = msg;
+
+        let pool = with_ctx(|a: &mut DbExecutor, _| a.pool());
+        let mut tx = pool.begin().await?;
+
+        let issue = lock_issue_in_progress(&mut tx, issue_id.clone()).await?;
+        // Retracting and voting again would change the vote
+        if !issue.allow_vote_change || issue.secret {
+            return Err(VoteError::ChangeNotAllowed.into());
+        }
+
+        // Proxies retract the principal's vote under the same delegation they vote with
+        let user_id = match on_behalf_of {
+            Some(principal_id) => {
+                if !has_delegation(
+                    &mut tx,
+                    &principal_id,
+                    &voter_id,
+                    &issue_id,
+                    issue.meeting_id.as_ref(),
+                )
+                .await?
+                {
+                    return Err(VoteError::NotDelegated.into());
+                }
+                principal_id
+            }
+            None => voter_id,
+        };
+
+        let vote = sqlx::query_as!(
+            InternalVote,
+            r#"
+            DELETE FROM votes WHERE user_id = $1 AND issue_id = $2
+            RETURNING id as "id?: _", alternative_id as "alternative_id: _", issue_id as "issue_id: _", user_id as "user_id: _", cast_by as "cast_by: _", weight
+            "#,
+            user_id.0,
+            issue_id.0,
+        )
+        .fetch_optional(&mut tx)
+        .await
+        .wrap_err("Got error while retracting vote")?
+        .ok_or(VoteError::NotVoted)?;
+        let (vote_count, total_weight) = count_votes_for_issue(&mut tx, issue_id.clone()).await?;
+
+        tx.commit().await?;
+        Ok(RetractedVote {
+            vote,
+            tally: VoteTally {
+                issue_id,
+                meeting_id: issue.meeting_id,
+                vote_count,
+                max_voters: issue.max_voters,
+                total_weight,
+                max_weight: issue.max_weight,
+                show_distribution: issue.show_distribution,
+            },
+        })
+    }
+}
+crate::span_message_async_impl!(RetractVote, DbExecutor);
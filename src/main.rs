@@ -1,7 +1,7 @@
 #![warn(clippy::all)]
 use actix_web::{App, HttpServer};
 use dotenv::dotenv;
-use tracing::{error, info, Level};
+use tracing::{error, info, warn, Level};
 use tracing_error::ErrorLayer;
 use tracing_subscriber::prelude::*;
 extern crate vaas_server;
@@ -30,9 +30,15 @@ async fn main() -> std::io::Result<()> {
     }
 
     let address = env::var("ADDRESS").unwrap_or_else(|_| "127.0.0.1:8080".to_string());
+    let settings = server::Settings {
+        demo_mode: env::var("DEMO_MODE").map_or(false, |value| value == "true"),
+    };
+    if settings.demo_mode {
+        warn!("Demo mode is enabled, admins can vote for other users");
+    }
     // Create Http server with websocket support
     info!("Starting server on {}", address);
-    HttpServer::new(move || App::new().configure(|app| server::configure(app)))
+    HttpServer::new(move || App::new().app_data(settings).configure(server::configure))
         .bind(address)?
         .run()
         .await
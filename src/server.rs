@@ -12,11 +12,19 @@ use tracing::{info, span, Level};
 use crate::{db, services, websocket};
 use sqlx::PgPool;
 
+/// Options of the server, set with `App::app_data`. Everything is off without them.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Settings {
+    /// Lets admins vote for other users to demonstrate the app
+    pub demo_mode: bool,
+}
+
 async fn ws_route(req: HttpRequest, stream: web::Payload) -> Result<HttpResponse, Error> {
     let span = span!(Level::INFO, "ws_route");
     let _enter = span.enter();
     register_request_actors();
-    let ws_client = websocket::WsClient::new();
+    let settings = req.app_data::<Settings>().copied().unwrap_or_default();
+    let ws_client = websocket::WsClient::new(settings);
     ws::start(ws_client, &req, stream)
 }
 
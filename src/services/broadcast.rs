@@ -1,18 +1,101 @@
-use super::{vote::BroadcastVote, Connect, Disconnect};
-use crate::websocket::WsClient;
+use super::{
+    issue::{BroadcastIssue, BroadcastIssueCreated, BroadcastResult},
+    session::BroadcastSessionsRevoked,
+    vote::BroadcastVote,
+    Connect, Disconnect,
+};
+use crate::{
+    db::{meeting::MeetingId, user::UserId},
+    websocket::WsClient,
+};
 use actix::prelude::*;
-use std::collections::HashSet;
+use std::collections::HashMap;
 use tracing::{debug, info};
 
+/// Moves the client into the meeting so it only receives broadcasts about the meeting's issues
+#[derive(Message, Clone)]
+#[rtype(result = "()")]
+pub struct JoinMeeting {
+    pub addr: Addr<WsClient>,
+    pub meeting_id: MeetingId,
+    pub user_id: UserId,
+    /// The user only attends the meeting as a voter on its roll
+    pub via_roll: bool,
+}
+
+/// Moves the client back to the lobby, where it receives broadcasts about the issues outside of any meeting
+#[derive(Message, Clone)]
+#[rtype(result = "()")]
+pub struct LeaveMeeting {
+    pub addr: Addr<WsClient>,
+}
+
+/// The user was removed from the voter roll of the meeting
+#[derive(Message, Clone)]
+#[rtype(result = "()")]
+pub struct VoterRemoved(pub MeetingId, pub UserId);
+
+/// Tells the client that it was moved back to the lobby
+#[derive(Message, Clone)]
+#[rtype(result = "()")]
+pub struct LeftMeeting(pub MeetingId);
+
+/// Broadcasts which only concern the clients in one meeting
+pub trait MeetingScoped {
+    /// No meeting means the issues outside of any meeting
+    fn meeting_id(&self) -> Option<&MeetingId>;
+
+    /// Client which caused the broadcast and is replied to directly
+    fn sender(&self) -> Option<&Addr<WsClient>> {
+        None
+    }
+}
+
+impl MeetingScoped for BroadcastVote {
+    fn meeting_id(&self) -> Option<&MeetingId> {
+        self.1.meeting_id.as_ref()
+    }
+}
+
+impl MeetingScoped for BroadcastIssue {
+    fn meeting_id(&self) -> Option<&MeetingId> {
+        self.0.meeting_id.as_ref()
+    }
+}
+
+impl MeetingScoped for BroadcastIssueCreated {
+    fn meeting_id(&self) -> Option<&MeetingId> {
+        self.0.meeting_id.as_ref()
+    }
+
+    fn sender(&self) -> Option<&Addr<WsClient>> {
+        Some(&self.1)
+    }
+}
+
+impl MeetingScoped for BroadcastResult {
+    fn meeting_id(&self) -> Option<&MeetingId> {
+        self.1.as_ref()
+    }
+}
+
+/// Meeting a client joined and the user it joined as
+struct JoinedMeeting {
+    meeting_id: MeetingId,
+    user_id: UserId,
+    via_roll: bool,
+}
+
 // Actor
 pub struct BroadcastActor {
-    clients: HashSet<Addr<WsClient>>,
+    /// Connected clients and the meeting they joined
+    clients: HashMap<Addr<WsClient>, Option<JoinedMeeting>>,
 }
 
 impl BroadcastActor {
     pub fn new() -> Self {
         BroadcastActor {
-            clients: HashSet::new(),
+            clients: HashMap::new(),
         }
     }
 }
@@ -38,11 +121,85 @@ impl Handler<Connect> for BroadcastActor {
 
     fn handle(&mut self, msg: Connect, _ctx: &mut Context<Self>) -> Self::Result {
         debug!("Adding new client to broadcast");
-        self.clients.insert(msg.addr);
+        self.clients.insert(msg.addr, None);
         Ok(())
     }
 }
 
+impl Handler<JoinMeeting> for BroadcastActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: JoinMeeting, _ctx: &mut Context<Self>) -> Self::Result {
+        debug!("Moving client into meeting");
+        let JoinMeeting {
+            addr,
+            meeting_id,
+            user_id,
+            via_roll,
+        } = msg;
+        self.clients.insert(
+            addr,
+            Some(JoinedMeeting {
+                meeting_id,
+                user_id,
+                via_roll,
+            }),
+        );
+    }
+}
+
+impl Handler<LeaveMeeting> for BroadcastActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: LeaveMeeting, _ctx: &mut Context<Self>) -> Self::Result {
+        // Disconnected clients stay removed
+        if let Some(joined) = self.clients.get_mut(&msg.addr) {
+            debug!("Moving client back to the lobby");
+            *joined = None;
+        }
+    }
+}
+
+impl Handler<VoterRemoved> for BroadcastActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: VoterRemoved, _ctx: &mut Context<Self>) -> Self::Result {
+        let VoterRemoved(meeting_id, user_id) = msg;
+        // Admins and chairs stay, they don't need to be on the roll
+        for (client, joined) in &mut self.clients {
+            let removed = joined.as_ref().map_or(false, |joined| {
+                joined.via_roll && joined.meeting_id == meeting_id && joined.user_id == user_id
+            });
+            if removed {
+                debug!("Moving removed voter back to the lobby");
+                *joined = None;
+                client.do_send(LeftMeeting(meeting_id.clone()));
+            }
+        }
+    }
+}
+
+impl Handler<BroadcastSessionsRevoked> for BroadcastActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: BroadcastSessionsRevoked, _ctx: &mut Context<Self>) -> Self::Result {
+        debug!(
+            "Broadcasting BroadcastSessionsRevoked to clients. Number of clients: {clients}",
+            clients = self.clients.len()
+        );
+        for (client, joined) in &mut self.clients {
+            // Logged out clients are moved out of their meeting before any further broadcast
+            if joined
+                .as_ref()
+                .map_or(false, |joined| joined.user_id == msg.0)
+            {
+                *joined = None;
+            }
+            client.do_send(msg.clone());
+        }
+    }
+}
+
 impl Handler<Disconnect> for BroadcastActor {
     type Result = ();
 
@@ -53,25 +210,33 @@ impl Handler<Disconnect> for BroadcastActor {
 }
 
 macro_rules! broadcast_handler {
-    ($message_type:ident) => {
+    ($message_type:ident, scoped) => {
         impl Handler<$message_type> for BroadcastActor  {
             type Result = ();
 
             fn handle(&mut self, msg: $message_type, _ctx: &mut Context<Self>) -> Self::Result {
+                let meeting_id = msg.meeting_id();
+                let sender = msg.sender();
                 debug!(
-                    "Broadcasting {type} to clients. Number of clients: {clients}",
+                    "Broadcasting {type} to clients in meeting {meeting:?}",
                     type = stringify!($message_type),
-                    clients = self.clients.len()
+                    meeting = meeting_id
                 );
-                for client in &self.clients {
-                    client.do_send(msg.clone());
+                for (client, joined) in &self.clients {
+                    let joined_meeting = joined.as_ref().map(|joined| &joined.meeting_id);
+                    if joined_meeting == meeting_id && Some(client) != sender {
+                        client.do_send(msg.clone());
+                    }
                 }
             }
         }
     };
 }
 
-broadcast_handler!(BroadcastVote);
+broadcast_handler!(BroadcastVote, scoped);
+broadcast_handler!(BroadcastIssue, scoped);
+broadcast_handler!(BroadcastIssueCreated, scoped);
+broadcast_handler!(BroadcastResult, scoped);
 
 impl SystemService for BroadcastActor {}
 impl Supervised for BroadcastActor {}
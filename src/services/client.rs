@@ -1,15 +1,73 @@
-use super::Login;
+use super::{Login, Registration, SetPassword};
 use crate::span::{AsyncSpanHandler, SpanMessage};
 use crate::{
     async_message_handler_with_span,
-    db::{user::UserByUsername, DbExecutor},
+    db::{
+        user::{
+            InternalUser, NewInternalUser, NewUser, RecordLoginAttempt, RegistrationError,
+            SetPasswordHash, UserCredentialsByUsername,
+        },
+        DbExecutor,
+    },
 };
 use actix::prelude::*;
 use color_eyre::{eyre::WrapErr, Report};
-use tracing::{debug, info};
+use std::fmt;
+use tracing::{debug, info, warn};
 
 // Types
 
+/// Every failed login gives the same error, so it doesn't reveal
+/// whether the user exists or is locked out
+#[derive(Debug)]
+pub enum LoginError {
+    InvalidCredentials,
+}
+
+impl fmt::Display for LoginError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoginError::InvalidCredentials => write!(f, "Invalid username or password"),
+        }
+    }
+}
+
+impl std::error::Error for LoginError {}
+
+fn argon2_config() -> argon2::Config<'static> {
+    argon2::Config {
+        variant: argon2::Variant::Argon2id,
+        ..Default::default()
+    }
+}
+
+pub fn hash_password(password: &str) -> Result<String, Report> {
+    let salt: [u8; 16] = rand::random();
+    argon2::hash_encoded(password.as_bytes(), &salt, &argon2_config())
+        .wrap_err("Failed to hash password")
+}
+
+fn verify_password(password_hash: &str, password: &str) -> Result<bool, Report> {
+    argon2::verify_encoded(password_hash, password.as_bytes())
+        .wrap_err("Failed to verify password hash")
+}
+
+const PASSWORD_MIN_LENGTH: usize = 8;
+
+fn validate_password(password: &str) -> Result<(), RegistrationError> {
+    if password.chars().count() < PASSWORD_MIN_LENGTH {
+        return Err(RegistrationError::InvalidPassword(
+            "must be at least 8 characters long",
+        ));
+    }
+    Ok(())
+}
+
+/// Verified when there is no usable hash, so failed logins all take as long.
+/// Uses the parameters of `argon2_config`.
+const DUMMY_PASSWORD_HASH: &str =
+    "$argon2id$v=19$m=4096,t=3,p=1$jotbX5cmIIB0ty4Ig9RKww$Q8+Wv7pdgRkFJ3o/pq2A/Po+HhxxnVNEuEgWicRVH20";
+
 // Actor
 pub struct ClientActor {}
 
@@ -35,12 +93,77 @@ impl Actor for ClientActor {
 
 async_message_handler_with_span!({
     impl AsyncSpanHandler<Login> for ClientActor {
-        async fn handle(msg: Login) -> Result<Option<crate::db::user::InternalUser>, Report> {
+        async fn handle(msg: Login) -> Result<InternalUser, Report> {
             debug!("Incoming login in ClientActor");
+            let db_executor = DbExecutor::from_registry();
+            let credentials = db_executor
+                .send(SpanMessage::new(UserCredentialsByUsername(msg.username)))
+                .await
+                .wrap_err("Failed to get user by username")??;
+            // The password is always verified so the response time doesn't reveal
+            // unknown or locked out users
+            let password_hash = credentials
+                .as_ref()
+                .and_then(|credentials| credentials.password_hash.as_deref());
+            let valid_password =
+                verify_password(password_hash.unwrap_or(DUMMY_PASSWORD_HASH), &msg.password)?
+                    && password_hash.is_some();
+            let credentials = match credentials {
+                Some(credentials) => credentials,
+                None => {
+                    warn!("Login attempt for unknown user");
+                    return Err(LoginError::InvalidCredentials.into());
+                }
+            };
+            if credentials.locked {
+                warn!("Login attempt for locked user");
+                return Err(LoginError::InvalidCredentials.into());
+            }
+            db_executor
+                .send(SpanMessage::new(RecordLoginAttempt {
+                    user_id: credentials.id.clone(),
+                    success: valid_password,
+                }))
+                .await??;
+            if !valid_password {
+                warn!("Failed login attempt");
+                return Err(LoginError::InvalidCredentials.into());
+            }
+            Ok(credentials.into_user())
+        }
+    }
+});
+
+async_message_handler_with_span!({
+    impl AsyncSpanHandler<Registration> for ClientActor {
+        async fn handle(msg: Registration) -> Result<InternalUser, Report> {
+            debug!("Incoming registration in ClientActor");
+            validate_password(&msg.password)?;
+            let password_hash = hash_password(&msg.password)?;
+            DbExecutor::from_registry()
+                .send(SpanMessage::new(NewUser(NewInternalUser {
+                    username: msg.username,
+                    password_hash,
+                })))
+                .await
+                .wrap_err("Failed to register user")?
+        }
+    }
+});
+
+async_message_handler_with_span!({
+    impl AsyncSpanHandler<SetPassword> for ClientActor {
+        async fn handle(msg: SetPassword) -> Result<(), Report> {
+            debug!("Incoming password change in ClientActor");
+            validate_password(&msg.password)?;
+            let password_hash = hash_password(&msg.password)?;
             DbExecutor::from_registry()
-                .send(SpanMessage::new(UserByUsername(msg.username)))
+                .send(SpanMessage::new(SetPasswordHash(
+                    msg.user_id,
+                    password_hash,
+                )))
                 .await
-                .wrap_err("Failed to get user by username")?
+                .wrap_err("Failed to set password")?
         }
     }
 });
@@ -1,7 +1,15 @@
-use crate::websocket::Issue;
+use super::broadcast::BroadcastActor;
+use crate::websocket::{IncomingIssue, WsClient};
 use crate::{
     db::{
-        self, alternative::InternalAlternative, issue::InternalIssueState, vote::InternalVote,
+        self,
+        alternative::{AlternativeId, InternalAlternative},
+        issue::{
+            InternalDecisionRules, InternalIssueState, InternalMajority, InternalVotingMethod,
+        },
+        meeting::MeetingId,
+        result::{AlternativeCount, InternalOutcome, InternalResult, InternalRound},
+        vote::{InternalChoice, InternalSecretWeight, InternalVote, VoteId},
         DbExecutor,
     },
     span::{AsyncSpanHandler, SpanMessage},
@@ -9,6 +17,7 @@ use crate::{
 use actix::prelude::*;
 use color_eyre::eyre::Report;
 use db::issue::IssueId;
+use std::collections::HashMap;
 use tracing::{debug, info};
 
 #[derive(Clone)]
@@ -21,6 +30,18 @@ pub struct InternalIssue {
     pub votes: Vec<InternalVote>,
     pub max_voters: i32,
     pub show_distribution: bool,
+    pub allow_vote_change: bool,
+    pub secret: bool,
+    pub voting_method: InternalVotingMethod,
+    pub min_choices: i32,
+    pub max_choices: Option<i32>,
+    /// Full ranked and approval ballots, the votes only hold the first choice
+    pub choices: HashMap<VoteId, Vec<AlternativeId>>,
+    /// Weight of secret ballots, which isn't stored on the ballots themselves
+    pub secret_weights: Vec<InternalSecretWeight>,
+    pub rules: InternalDecisionRules,
+    pub max_weight: Option<i32>,
+    pub meeting_id: Option<MeetingId>,
 }
 
 impl InternalIssue {
@@ -28,16 +49,35 @@ impl InternalIssue {
         issue: db::issue::InternalIssue,
         alternatives: Vec<db::alternative::InternalAlternative>,
         votes: Vec<db::vote::InternalVote>,
+        choices: Vec<InternalChoice>,
+        secret_weights: Vec<InternalSecretWeight>,
     ) -> Self {
+        let mut ballots: HashMap<VoteId, Vec<AlternativeId>> = HashMap::new();
+        for choice in choices {
+            ballots
+                .entry(choice.vote_id)
+                .or_default()
+                .push(choice.alternative_id);
+        }
         Self {
+            rules: issue.rules(),
             id: issue.id,
             title: issue.title,
             description: issue.description,
             state: issue.state,
             max_voters: issue.max_voters,
             show_distribution: issue.show_distribution,
+            allow_vote_change: issue.allow_vote_change,
+            secret: issue.secret,
+            voting_method: issue.voting_method,
+            min_choices: issue.min_choices,
+            max_choices: issue.max_choices,
+            max_weight: issue.max_weight,
+            meeting_id: issue.meeting_id,
             alternatives,
             votes,
+            choices: ballots,
+            secret_weights,
         }
     }
 }
@@ -60,76 +100,413 @@ impl Actor for IssueService {
     type Context = Context<Self>;
 }
 
+/// Active issue of the meeting, or of the issues outside of any meeting
 #[derive(Message)]
 #[rtype(result = "Result<Option<InternalIssue>, Report>")]
-pub struct ActiveIssue;
+pub struct ActiveIssue(pub Option<MeetingId>);
+
+/// Retrieves alternatives and votes belonging to the issue
+pub async fn load_issue(issue: db::issue::InternalIssue) -> Result<InternalIssue, Report> {
+    debug!(
+        "Issue found, retrieving alternatives and votes {:#?}",
+        id = issue.id
+    );
+    let (alternatives, votes, choices, secret_weights) = tokio::join!(
+        DbExecutor::from_registry().send(SpanMessage::new(
+            db::alternative::AlternativesForIssueId(issue.id.clone(),)
+        )),
+        DbExecutor::from_registry()
+            .send(SpanMessage::new(db::vote::VotesForIssue(issue.id.clone(),))),
+        DbExecutor::from_registry().send(SpanMessage::new(db::vote::ChoicesForIssue(
+            issue.id.clone(),
+        ))),
+        DbExecutor::from_registry().send(SpanMessage::new(db::vote::SecretWeightsForIssue(
+            issue.id.clone(),
+        ))),
+    );
+    let alternatives = alternatives??;
+    let votes = votes??;
+    let choices = choices??;
+    let secret_weights = secret_weights??;
+    debug!("Alternatives found {}", alternatives.len());
+    debug!("Votes found {}", votes.len());
+    Ok(InternalIssue::from_db(
+        issue,
+        alternatives,
+        votes,
+        choices,
+        secret_weights,
+    ))
+}
 
 #[async_trait::async_trait]
 impl AsyncSpanHandler<ActiveIssue> for IssueService {
-    async fn handle(_msg: ActiveIssue) -> Result<Option<InternalIssue>, Report> {
+    async fn handle(msg: ActiveIssue) -> Result<Option<InternalIssue>, Report> {
         info!("Sending active issue");
         let issue: Option<db::issue::InternalIssue> = DbExecutor::from_registry()
-            .send(SpanMessage::new(db::issue::ActiveIssue()))
+            .send(SpanMessage::new(db::issue::ActiveIssue(msg.0)))
             .await??;
         match issue {
-            Some(issue) => {
-                debug!(
-                    "Issue found, retrieving alternatives and votes {:#?}",
-                    id = issue.id
-                );
-                let (alternatives, votes) = tokio::join!(
-                    DbExecutor::from_registry().send(SpanMessage::new(
-                        db::alternative::AlternativesForIssueId(issue.id.clone(),)
-                    )),
-                    DbExecutor::from_registry()
-                        .send(SpanMessage::new(db::vote::VotesForIssue(issue.id.clone(),))),
-                );
-                let alternatives = alternatives??;
-                let votes = votes??;
-                debug!("Alternatives found {}", alternatives.len());
-                debug!("Votes found {}", votes.len());
-                Ok(Some(InternalIssue::from_db(issue, alternatives, votes)))
-            }
+            Some(issue) => Ok(Some(load_issue(issue).await?)),
             None => Ok(None),
         }
     }
 }
 crate::span_message_async_impl!(ActiveIssue, IssueService);
 
+/// Creates the issue for the client, which is replied to instead of sent the broadcast
 #[derive(Message)]
-#[rtype(result = "Result<Option<InternalIssue>, Report>")]
-pub struct NewIssue(pub Issue);
+#[rtype(result = "Result<InternalIssue, Report>")]
+pub struct NewIssue(pub IncomingIssue, pub Addr<WsClient>);
 
 #[async_trait::async_trait]
 impl AsyncSpanHandler<NewIssue> for IssueService {
-    async fn handle(_msg: NewIssue) -> Result<Option<InternalIssue>, Report> {
+    async fn handle(msg: NewIssue) -> Result<InternalIssue, Report> {
         info!("Creating new issue");
-        let issue: Option<db::issue::InternalIssue> = DbExecutor::from_registry()
-            .send(SpanMessage::new(db::issue::NewIssue(_msg.0)))
+        let NewIssue(issue, creator) = msg;
+        let (issue, alternatives) = DbExecutor::from_registry()
+            .send(SpanMessage::new(db::issue::NewIssue(issue)))
             .await??;
-        debug!("pog back from db stuff phew");
-        match issue {
-            Some(issue) => {
-                debug!("some back from db");
-                Ok(Some(InternalIssue {
-                    id: issue.id,
-                    alternatives: Vec::new(),
-                    description: issue.description,
-                    title: issue.title,
-                    max_voters: issue.max_voters,
-                    show_distribution: issue.show_distribution,
-                    state: issue.state,
-                    votes: Vec::new(),
-                }))
+        debug!("Created issue with {} alternatives", alternatives.len());
+        let issue = InternalIssue::from_db(issue, alternatives, Vec::new(), Vec::new(), Vec::new());
+        BroadcastActor::from_registry().do_send(BroadcastIssueCreated(issue.clone(), creator));
+        Ok(issue)
+    }
+}
+crate::span_message_async_impl!(NewIssue, IssueService);
+
+/// The issue clients are shown was activated or changed its state
+#[derive(Message, Clone)]
+#[rtype(result = "()")]
+pub struct BroadcastIssue(pub InternalIssue);
+
+/// A new issue was added, clients keep showing their current issue.
+/// Its creator is left out, it gets the issue in reply to its request.
+#[derive(Message, Clone)]
+#[rtype(result = "()")]
+pub struct BroadcastIssueCreated(pub InternalIssue, pub Addr<WsClient>);
+
+#[derive(Message, Clone)]
+#[rtype(result = "()")]
+pub struct BroadcastResult(pub InternalResult, pub Option<MeetingId>);
+
+/// Alternatives of counted votes, and the number and summed weight of these votes.
+/// Every vote is a ballot of its own unless it is secret.
+struct Ballot<'a> {
+    choices: &'a [AlternativeId],
+    count: i64,
+    weight: i64,
+}
+
+impl InternalIssue {
+    /// Counts the votes and their weight per alternative and finds the winners by weight.
+    /// Ranked issues are counted in instant runoff rounds, the counts are those of the last round.
+    /// Approval issues count every selected alternative of a ballot.
+    /// Secret ballots are counted by their summed weight per choices.
+    pub fn result(&self) -> InternalResult {
+        let ballots: Vec<Ballot> = self
+            .votes
+            .iter()
+            .filter_map(|vote| {
+                let first_choice = vote.alternative_id.as_ref()?;
+                Some(Ballot {
+                    choices: match vote.id.as_ref().and_then(|id| self.choices.get(id)) {
+                        Some(choices) => choices.as_slice(),
+                        None => std::slice::from_ref(first_choice),
+                    },
+                    count: 1,
+                    weight: i64::from(vote.weight?),
+                })
+            })
+            .chain(
+                self.secret_weights
+                    .iter()
+                    .filter(|secret| !secret.choices.is_empty())
+                    .map(|secret| Ballot {
+                        choices: &secret.choices,
+                        count: i64::from(secret.ballots),
+                        weight: secret.weight,
+                    }),
+            )
+            .collect();
+        let rounds = match self.voting_method {
+            InternalVotingMethod::Plurality | InternalVotingMethod::Approval => Vec::new(),
+            InternalVotingMethod::Ranked => self.instant_runoff(&ballots),
+        };
+        let counts = match rounds.last() {
+            Some(round) => round.counts.clone(),
+            None if self.voting_method == InternalVotingMethod::Approval => {
+                self.count_approvals(&ballots)
             }
             None => {
-                debug!("none back from db");
-                Ok(None)
+                let alternative_ids: Vec<AlternativeId> = self
+                    .alternatives
+                    .iter()
+                    .map(|alternative| alternative.id.clone())
+                    .collect();
+                self.count_round(&ballots, &alternative_ids)
+            }
+        };
+        let abstentions = self
+            .votes
+            .iter()
+            .filter(|vote| vote.alternative_id.is_none())
+            .count() as i64;
+        let abstention_weight = self
+            .votes
+            .iter()
+            .filter(|vote| vote.alternative_id.is_none())
+            .filter_map(|vote| vote.weight.map(i64::from))
+            .chain(
+                self.secret_weights
+                    .iter()
+                    .filter(|secret| secret.choices.is_empty())
+                    .map(|secret| secret.weight),
+            )
+            .sum();
+        let winners = match counts.iter().map(|count| count.weight).max() {
+            Some(max) if max > 0 => counts
+                .iter()
+                .filter(|count| count.weight == max)
+                .map(|count| count.alternative_id.clone())
+                .collect(),
+            _ => Vec::new(),
+        };
+        // Approvals are measured against the ballots rather than the sum of approvals
+        let counted = match self.voting_method {
+            InternalVotingMethod::Approval => ballots.iter().map(|ballot| ballot.weight).sum(),
+            _ => counts.iter().map(|count| count.weight).sum(),
+        };
+        let vote_count = self.votes.len() as i64;
+        let total_weight = self
+            .votes
+            .iter()
+            .filter_map(|vote| vote.weight.map(i64::from))
+            .chain(self.secret_weights.iter().map(|secret| secret.weight))
+            .sum();
+        let outcome = self.outcome(
+            &counts,
+            counted,
+            abstention_weight,
+            vote_count,
+            total_weight,
+        );
+        InternalResult {
+            issue_id: self.id.clone(),
+            counts,
+            abstentions,
+            vote_count,
+            max_voters: self.max_voters,
+            abstention_weight,
+            total_weight,
+            max_weight: self.max_weight,
+            winners,
+            outcome,
+            rounds,
+        }
+    }
+
+    /// Sums up the ballots and their weight for every alternative which they count for
+    fn count_alternatives(
+        &self,
+        ballots: &[Ballot],
+        counts_for: impl Fn(&Ballot, &AlternativeId) -> bool,
+    ) -> Vec<AlternativeCount> {
+        self.alternatives
+            .iter()
+            .map(|alternative| {
+                let (count, weight) = ballots
+                    .iter()
+                    .filter(|ballot| counts_for(ballot, &alternative.id))
+                    .fold((0, 0), |(count, weight), ballot| {
+                        (count + ballot.count, weight + ballot.weight)
+                    });
+                AlternativeCount {
+                    alternative_id: alternative.id.clone(),
+                    count,
+                    weight,
+                }
+            })
+            .collect()
+    }
+
+    /// Counts every ballot for its most preferred alternative which is still in the running
+    fn count_round(
+        &self,
+        ballots: &[Ballot],
+        continuing: &[AlternativeId],
+    ) -> Vec<AlternativeCount> {
+        self.count_alternatives(ballots, |ballot, alternative_id| {
+            ballot
+                .choices
+                .iter()
+                .find(|choice| continuing.contains(choice))
+                == Some(alternative_id)
+        })
+    }
+
+    fn count_approvals(&self, ballots: &[Ballot]) -> Vec<AlternativeCount> {
+        self.count_alternatives(ballots, |ballot, alternative_id| {
+            ballot.choices.contains(alternative_id)
+        })
+    }
+
+    /// Eliminates the alternatives with the least weight until one has a majority
+    /// of the ballots which aren't exhausted, or all remaining alternatives are tied
+    fn instant_runoff(&self, ballots: &[Ballot]) -> Vec<InternalRound> {
+        let mut continuing: Vec<AlternativeId> = self
+            .alternatives
+            .iter()
+            .map(|alternative| alternative.id.clone())
+            .collect();
+        let total_weight: i64 = ballots.iter().map(|ballot| ballot.weight).sum();
+        let mut rounds = Vec::new();
+        loop {
+            let counts = self.count_round(ballots, &continuing);
+            let remaining: Vec<&AlternativeCount> = counts
+                .iter()
+                .filter(|count| continuing.contains(&count.alternative_id))
+                .collect();
+            let counted: i64 = remaining.iter().map(|count| count.count).sum();
+            let counted_weight: i64 = remaining.iter().map(|count| count.weight).sum();
+            let most = remaining
+                .iter()
+                .map(|count| count.weight)
+                .max()
+                .unwrap_or(0);
+            let fewest = remaining
+                .iter()
+                .map(|count| count.weight)
+                .min()
+                .unwrap_or(0);
+            let eliminated: Vec<AlternativeId> = if most * 2 > counted_weight || fewest == most {
+                Vec::new()
+            } else {
+                remaining
+                    .iter()
+                    .filter(|count| count.weight == fewest)
+                    .map(|count| count.alternative_id.clone())
+                    .collect()
+            };
+            let finished = eliminated.is_empty();
+            continuing.retain(|alternative_id| !eliminated.contains(alternative_id));
+            rounds.push(InternalRound {
+                counts,
+                exhausted: ballots.iter().map(|ballot| ballot.count).sum::<i64>() - counted,
+                exhausted_weight: total_weight - counted_weight,
+                eliminated,
+            });
+            if finished {
+                return rounds;
             }
         }
     }
+
+    /// Applies the decision rules of the issue to the counted vote weight.
+    /// Quorum is measured in weight if the issue has a max weight and in voters otherwise.
+    fn outcome(
+        &self,
+        counts: &[AlternativeCount],
+        mut counted: i64,
+        abstention_weight: i64,
+        vote_count: i64,
+        total_weight: i64,
+    ) -> InternalOutcome {
+        let rules = &self.rules;
+        let (turnout, max) = match self.max_weight {
+            Some(max_weight) => (total_weight, max_weight),
+            None => (vote_count, self.max_voters),
+        };
+        if turnout * 100 < i64::from(rules.quorum_percent) * i64::from(max) {
+            return InternalOutcome::NoQuorum;
+        }
+        let mut sorted: Vec<i64> = counts.iter().map(|count| count.weight).collect();
+        sorted.sort_unstable_by(|a, b| b.cmp(a));
+        let top = sorted.first().copied().unwrap_or(0);
+        let second = sorted.get(1).copied().unwrap_or(0);
+        if rules.count_abstentions {
+            counted += abstention_weight;
+        }
+        let passed = top > 0
+            && match rules.majority {
+                InternalMajority::Simple => top > second,
+                InternalMajority::Absolute => top * 2 > counted,
+                InternalMajority::Qualified {
+                    numerator,
+                    denominator,
+                } => top * i64::from(denominator) >= counted * i64::from(numerator),
+            };
+        if passed {
+            InternalOutcome::Passed
+        } else {
+            InternalOutcome::Failed
+        }
+    }
 }
-crate::span_message_async_impl!(NewIssue, IssueService);
+
+/// Broadcasts the issue if it is the one shown to the clients in its meeting
+pub async fn broadcast_if_current(issue: &InternalIssue) -> Result<(), Report> {
+    let current = DbExecutor::from_registry()
+        .send(SpanMessage::new(db::issue::ActiveIssue(
+            issue.meeting_id.clone(),
+        )))
+        .await??;
+    if current.map_or(false, |current| current.id == issue.id) {
+        BroadcastActor::from_registry().do_send(BroadcastIssue(issue.clone()));
+    }
+    Ok(())
+}
+
+/// Computes, stores and broadcasts the result of a finished issue
+pub async fn publish_result(issue: &InternalIssue) -> Result<(), Report> {
+    let result = issue.result();
+    info!(winners = result.winners.len(), "Publishing result");
+    DbExecutor::from_registry()
+        .send(SpanMessage::new(db::result::SaveResult(result.clone())))
+        .await??;
+    BroadcastActor::from_registry().do_send(BroadcastResult(result, issue.meeting_id.clone()));
+    Ok(())
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<InternalIssue, Report>")]
+pub struct UpdateIssueState(pub db::issue::UpdateIssueState);
+
+#[async_trait::async_trait]
+impl AsyncSpanHandler<UpdateIssueState> for IssueService {
+    async fn handle(msg: UpdateIssueState) -> Result<InternalIssue, Report> {
+        let UpdateIssueState(update) = msg;
+        info!("Updating issue state {:?}", update.transition);
+        let issue = DbExecutor::from_registry()
+            .send(SpanMessage::new(update))
+            .await??;
+        let issue = load_issue(issue).await?;
+        broadcast_if_current(&issue).await?;
+        if issue.state == InternalIssueState::Finished {
+            publish_result(&issue).await?;
+        }
+        Ok(issue)
+    }
+}
+crate::span_message_async_impl!(UpdateIssueState, IssueService);
+
+#[derive(Message)]
+#[rtype(result = "Result<InternalIssue, Report>")]
+pub struct SetActiveIssue(pub IssueId);
+
+#[async_trait::async_trait]
+impl AsyncSpanHandler<SetActiveIssue> for IssueService {
+    async fn handle(msg: SetActiveIssue) -> Result<InternalIssue, Report> {
+        info!("Switching active issue");
+        let issue = DbExecutor::from_registry()
+            .send(SpanMessage::new(db::issue::SetActiveIssue(msg.0)))
+            .await??;
+        let issue = load_issue(issue).await?;
+        BroadcastActor::from_registry().do_send(BroadcastIssue(issue.clone()));
+        Ok(issue)
+    }
+}
+crate::span_message_async_impl!(SetActiveIssue, IssueService);
 
 impl Supervised for IssueService {}
 impl ArbiterService for IssueService {}
@@ -1,4 +1,5 @@
 use crate::async_message_handler_with_span;
+use crate::db::{issue::InternalIssueState, result::ResultForIssue, DbExecutor};
 use crate::span::{AsyncSpanHandler, SpanMessage};
 use crate::websocket::WsClient;
 use actix::prelude::*;
@@ -30,9 +31,25 @@ impl fmt::Debug for Connect {
 }
 
 #[derive(Message, Clone)]
-#[rtype(result = "Result<Option<crate::db::user::InternalUser>, Report>")]
+#[rtype(result = "Result<crate::db::user::InternalUser, Report>")]
 pub struct Login {
     pub username: String,
+    pub password: String,
+}
+
+#[derive(Message, Clone)]
+#[rtype(result = "Result<crate::db::user::InternalUser, Report>")]
+pub struct Registration {
+    pub username: String,
+    pub password: String,
+}
+
+/// Sets the password of an existing user
+#[derive(Message, Clone)]
+#[rtype(result = "Result<(), Report>")]
+pub struct SetPassword {
+    pub user_id: crate::db::user::UserId,
+    pub password: String,
 }
 
 #[derive(Message, Clone)]
@@ -77,16 +94,28 @@ impl Actor for Service {
 async fn handle_connect(msg: Connect) -> Result<(), Report> {
     info!("Test test");
     let res = IssueService::from_registry()
-        .send(SpanMessage::new(issue::ActiveIssue))
+        .send(SpanMessage::new(issue::ActiveIssue(None)))
         .await??;
     match res {
         Some(issue) => {
+            let finished = issue.state == InternalIssueState::Finished;
+            let issue_id = issue.id.clone();
             msg.addr
                 .send(ActiveIssue(issue))
                 .await
                 .wrap_err("Failed to send active issue")?;
-            // Send existing vote ?
-            // no because we havent logged in yet
+            // The user's own vote is sent in the snapshot after logging in
+            if finished {
+                let result = DbExecutor::from_registry()
+                    .send(SpanMessage::new(ResultForIssue(issue_id)))
+                    .await??;
+                if let Some(result) = result {
+                    msg.addr
+                        .send(issue::BroadcastResult(result, None))
+                        .await
+                        .wrap_err("Failed to send result")?;
+                }
+            }
         }
         None => {
             error!("No active issue found! Missing sample data?");
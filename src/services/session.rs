@@ -1,3 +1,4 @@
+use super::broadcast::BroadcastActor;
 use crate::async_message_handler_with_span;
 use crate::{
     db::{
@@ -63,3 +64,43 @@ async_message_handler_with_span!({
         }
     }
 });
+
+#[derive(Message, Clone)]
+#[rtype(result = "Result<(), Report>")]
+pub struct Logout(pub SessionId);
+
+async_message_handler_with_span!({
+    impl AsyncSpanHandler<Logout> for SessionActor {
+        async fn handle(msg: Logout) -> Result<(), Report> {
+            debug!("Logging out session");
+            DbExecutor::from_registry()
+                .send(SpanMessage::new(db::session::DeleteSession(msg.0)))
+                .await?
+        }
+    }
+});
+
+#[derive(Message, Clone)]
+#[rtype(result = "()")]
+pub struct BroadcastSessionsRevoked(pub UserId);
+
+#[derive(Message, Clone)]
+#[rtype(result = "Result<(), Report>")]
+pub struct RevokeUserSessions(pub UserId);
+
+async_message_handler_with_span!({
+    impl AsyncSpanHandler<RevokeUserSessions> for SessionActor {
+        async fn handle(msg: RevokeUserSessions) -> Result<(), Report> {
+            let user_id = msg.0;
+            let revoked = DbExecutor::from_registry()
+                .send(SpanMessage::new(db::session::DeleteUserSessions(
+                    user_id.clone(),
+                )))
+                .await??;
+            info!(revoked, "Revoked sessions for user");
+            // Connected clients still hold the session in memory
+            BroadcastActor::from_registry().do_send(BroadcastSessionsRevoked(user_id));
+            Ok(())
+        }
+    }
+});
@@ -1,8 +1,14 @@
 use super::broadcast::BroadcastActor;
+use super::issue::{broadcast_if_current, load_issue, publish_result};
 use crate::span::{AsyncSpanHandler, SpanMessage};
 use crate::{
     async_message_handler_with_span,
-    db::{self, alternative::AlternativeId, user::UserId, vote::InternalVote, DbExecutor},
+    db::{
+        self,
+        user::UserId,
+        vote::{InternalVote, VoteTally},
+        DbExecutor,
+    },
 };
 use actix::prelude::*;
 use color_eyre::eyre::Report;
@@ -11,11 +17,34 @@ use tracing::{debug, info};
 
 #[derive(Message)]
 #[rtype(result = "Result<(), Report>")]
-pub struct IncomingVoteMessage(pub UserId, pub IssueId, pub AlternativeId);
+pub struct IncomingVoteMessage(pub db::vote::AddVote);
+
+#[derive(Message)]
+#[rtype(result = "Result<(), Report>")]
+pub struct RetractVote(pub UserId, pub IssueId, pub Option<UserId>);
+
+#[derive(Clone)]
+pub enum VoteChange {
+    Added(InternalVote),
+    /// The user changed their existing vote
+    Replaced(InternalVote),
+    /// The user withdrew their vote
+    Retracted(InternalVote),
+}
+
+impl VoteChange {
+    pub fn vote(&self) -> &InternalVote {
+        match self {
+            VoteChange::Added(vote) | VoteChange::Replaced(vote) | VoteChange::Retracted(vote) => {
+                vote
+            }
+        }
+    }
+}
 
 #[derive(Message, Clone)]
 #[rtype(result = "()")]
-pub struct BroadcastVote(pub InternalVote);
+pub struct BroadcastVote(pub VoteChange, pub VoteTally);
 
 // Actor
 
@@ -45,18 +74,55 @@ async_message_handler_with_span!({
     impl AsyncSpanHandler<IncomingVoteMessage> for VoteActor {
         async fn handle(msg: IncomingVoteMessage) -> Result<(), Report> {
             debug!("VoteActor handling IncomingVoteMessage");
-            let IncomingVoteMessage(user_id, issue_id, alternative_id) = msg;
+            let IncomingVoteMessage(add) = msg;
+            let issue_id = add.issue_id.clone();
+
+            let added = DbExecutor::from_registry()
+                .send(SpanMessage::new(add))
+                .await??;
 
-            let vote = DbExecutor::from_registry()
-                .send(SpanMessage::new(db::vote::AddVote(
+            let broadcast = BroadcastActor::from_registry();
+            let change = if added.replaced {
+                VoteChange::Replaced(added.vote)
+            } else {
+                VoteChange::Added(added.vote)
+            };
+            broadcast.do_send(BroadcastVote(change, added.tally));
+
+            if added.issue_finished {
+                info!("Max voters reached, broadcasting finished issue");
+                let issue = DbExecutor::from_registry()
+                    .send(SpanMessage::new(db::issue::IssueById(issue_id)))
+                    .await??;
+                if let Some(issue) = issue {
+                    let issue = load_issue(issue).await?;
+                    broadcast_if_current(&issue).await?;
+                    publish_result(&issue).await?;
+                }
+            }
+            Ok(())
+        }
+    }
+});
+
+async_message_handler_with_span!({
+    impl AsyncSpanHandler<RetractVote> for VoteActor {
+        async fn handle(msg: RetractVote) -> Result<(), Report> {
+            debug!("VoteActor handling RetractVote");
+            let RetractVote(user_id, issue_id, on_behalf_of) = msg;
+
+            let retracted = DbExecutor::from_registry()
+                .send(SpanMessage::new(db::vote::RetractVote(
                     user_id,
                     issue_id,
-                    alternative_id,
+                    on_behalf_of,
                 )))
                 .await??;
 
-            let broadcast = BroadcastActor::from_registry();
-            broadcast.do_send(BroadcastVote(vote));
+            BroadcastActor::from_registry().do_send(BroadcastVote(
+                VoteChange::Retracted(retracted.vote),
+                retracted.tally,
+            ));
             Ok(())
         }
     }
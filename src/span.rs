@@ -36,16 +36,16 @@ where
 #[macro_export]
 macro_rules! span_message_async_impl {
     ($message_type:ident, $actor:ident) => {
-        impl actix::Handler<crate::span::SpanMessage<$message_type>> for $actor {
+        impl actix::Handler<$crate::span::SpanMessage<$message_type>> for $actor {
             type Result = actix::ResponseActFuture<Self, <$message_type as actix::Message>::Result>;
             fn handle(
                 &mut self,
-                msg: crate::span::SpanMessage<$message_type>,
+                msg: $crate::span::SpanMessage<$message_type>,
                 _ctx: &mut actix::Context<Self>,
             ) -> Self::Result {
                 use actix_interop::FutureInterop;
                 use tracing_futures::Instrument;
-                let crate::span::SpanMessage { span, msg } = msg;
+                let $crate::span::SpanMessage { span, msg } = msg;
                 let _enter = span.enter();
                 <Self as AsyncSpanHandler<$message_type>>::handle(msg)
                     .in_current_span()
@@ -58,7 +58,7 @@ macro_rules! span_message_async_impl {
 #[macro_export]
 macro_rules ! async_message_handler_with_span {
     ({impl AsyncSpanHandler<$M:ident> for $A:ident $t:tt}) => {
-        crate::span_message_async_impl!($M, $A);
+        $crate::span_message_async_impl!($M, $A);
         #[async_trait::async_trait]
         impl AsyncSpanHandler<$M> for $A
             $t
@@ -1,49 +1,190 @@
 use crate::services;
-use crate::services::broadcast::BroadcastActor;
+use crate::services::broadcast::{
+    BroadcastActor, JoinMeeting, LeaveMeeting, LeftMeeting, VoterRemoved,
+};
 use crate::services::client::ClientActor;
-use crate::services::issue::{IssueService, NewIssue};
-use crate::services::vote::{BroadcastVote, IncomingVoteMessage, VoteActor};
-use crate::services::{Login, Service};
-use crate::{db, db::DbExecutor, span::SpanMessage};
+use crate::services::issue::{
+    self, BroadcastIssue, BroadcastIssueCreated, BroadcastResult, IssueService, NewIssue,
+    SetActiveIssue, UpdateIssueState,
+};
+use crate::services::vote::{
+    BroadcastVote, IncomingVoteMessage, RetractVote, VoteActor, VoteChange,
+};
+use crate::services::{client::LoginError, Login, Registration, Service, SetPassword};
+use crate::{db, db::DbExecutor, server::Settings, span::SpanMessage};
 use actix::prelude::*;
 use actix_interop::{with_ctx, FutureInterop};
 use actix_web_actors::ws;
-use color_eyre::eyre::{eyre, Report, WrapErr};
+use color_eyre::eyre::{Report, WrapErr};
 use db::{
     alternative::AlternativeId,
-    issue::IssueId,
+    delegation::{DelegationError, GrantDelegation, RevokeDelegation},
+    issue::IssueError,
+    issue::{
+        InternalDecisionRules, InternalIssueState, InternalMajority, InternalVotingMethod, IssueId,
+        IssueTransition,
+    },
+    meeting::{
+        AddVoter, InternalMeeting, MeetingById, MeetingError, MeetingId, NewMeeting, OnVoterRoll,
+        RemoveVoter,
+    },
+    result::{AlternativeCount, InternalOutcome, InternalResult, InternalRound, ResultForIssue},
     session::{InternalSession, SessionId},
-    user::NewInternalUser,
-    user::NewUser,
-    user::{UserById, UserId},
-    vote::{InternalVote, VoteId},
+    user::{
+        InternalUserRole, PasswordError, RegistrationError, SetVoteWeight, UserById, UserId,
+        VoteWeightError,
+    },
+    vote::{AddVote, InternalBallot, InternalVote, VoteError, VoteForUser, VoteId},
 };
 use serde::{Deserialize, Serialize};
-use services::session::{SaveSession, SessionActor, SessionById};
+use services::session::{
+    BroadcastSessionsRevoked, Logout, RevokeUserSessions, SaveSession, SessionActor, SessionById,
+};
+use std::fmt;
 use tracing::{debug, error, info, span, warn, Level};
 
 #[derive(Serialize, Deserialize)]
 pub struct IncomingLogin {
     pub username: String,
+    pub password: String,
 }
 #[derive(Serialize, Deserialize)]
 pub struct IncomingVote {
-    pub alternative_id: AlternativeId,
+    #[serde(default)]
+    pub alternative_id: Option<AlternativeId>,
+    /// Selected alternatives on approval issues, instead of a single alternative
+    #[serde(default)]
+    pub alternative_ids: Vec<AlternativeId>,
+    pub issue_id: IssueId,
+    /// Admins may vote for any user while the server runs in demo mode
+    pub user_id: Option<UserId>,
+    /// Principal who granted the voter a proxy
+    #[serde(default)]
+    pub on_behalf_of: Option<UserId>,
+}
+
+/// Ballot for issues using the ranked voting method
+#[derive(Serialize, Deserialize)]
+pub struct IncomingRankedVote {
+    pub issue_id: IssueId,
+    /// Alternatives in order of preference, unranked alternatives get no vote
+    pub ranking: Vec<AlternativeId>,
+    #[serde(default)]
+    pub on_behalf_of: Option<UserId>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct IncomingAbstain {
+    pub issue_id: IssueId,
+    #[serde(default)]
+    pub on_behalf_of: Option<UserId>,
+}
+
+/// Lets the proxy vote for the logged in user on the issue, on the issues of the meeting
+/// or on all issues without either
+#[derive(Serialize, Deserialize)]
+pub struct IncomingGrantDelegation {
+    pub proxy_id: UserId,
+    #[serde(default)]
+    pub issue_id: Option<IssueId>,
+    #[serde(default)]
+    pub meeting_id: Option<MeetingId>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct IncomingRevokeDelegation {
+    #[serde(default)]
+    pub issue_id: Option<IssueId>,
+    #[serde(default)]
+    pub meeting_id: Option<MeetingId>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct IncomingRetractVote {
     pub issue_id: IssueId,
-    pub user_id: Option<UserId>, // Used for fake voting
+    #[serde(default)]
+    pub on_behalf_of: Option<UserId>,
 }
 #[derive(Serialize, Deserialize)]
 pub struct IncomingCreateIssue {
-    pub issue: Issue,
+    pub issue: IncomingIssue,
+}
+#[derive(Serialize, Deserialize)]
+pub struct IncomingIssueStateChange {
+    pub issue_id: IssueId,
+}
+#[derive(Serialize, Deserialize)]
+pub struct IncomingResetIssue {
+    pub issue_id: IssueId,
+    /// Required to reset a finished issue, which discards its votes and result
+    #[serde(default)]
+    pub confirm: bool,
+}
+#[derive(Serialize, Deserialize)]
+pub struct IncomingActivateIssue {
+    pub issue_id: IssueId,
+}
+#[derive(Serialize, Deserialize)]
+pub struct IncomingCreateMeeting {
+    pub title: String,
+}
+
+/// Switches the client to the active issue of the meeting and its broadcasts
+#[derive(Serialize, Deserialize)]
+pub struct IncomingJoinMeeting {
+    pub meeting_id: MeetingId,
+}
+
+/// Adds the user to or removes them from the voter roll of the meeting
+#[derive(Serialize, Deserialize)]
+pub struct IncomingMeetingVoter {
+    pub meeting_id: MeetingId,
+    pub user_id: UserId,
 }
+
 #[derive(Serialize, Deserialize)]
 pub struct IncomingReconnect {
     pub session_id: SessionId,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct IncomingRevokeUserSessions {
+    pub user_id: UserId,
+}
+
+/// Sets how many times the votes of the user count
+#[derive(Serialize, Deserialize)]
+pub struct IncomingSetVoteWeight {
+    pub user_id: UserId,
+    pub weight: i32,
+}
+
+/// Sets the password of the user, such as one added without a password
+#[derive(Serialize, Deserialize)]
+pub struct IncomingSetPassword {
+    pub user_id: UserId,
+    pub password: String,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct IncomingRegistration {
     pub username: String,
+    pub password: String,
+}
+
+/// Client chosen id used to correlate replies with the request
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum RequestId {
+    Number(u64),
+    String(String),
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct IncomingEnvelope {
+    pub request_id: Option<RequestId>,
+    #[serde(flatten)]
+    pub message: IncomingMessage,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -51,17 +192,51 @@ pub struct IncomingRegistration {
 pub enum IncomingMessage {
     #[serde(rename = "vote")]
     Vote(IncomingVote),
+    #[serde(rename = "vote_ranked")]
+    RankedVote(IncomingRankedVote),
+    #[serde(rename = "abstain")]
+    Abstain(IncomingAbstain),
+    #[serde(rename = "vote_retract")]
+    RetractVote(IncomingRetractVote),
     #[serde(rename = "login")]
     Login(IncomingLogin),
     #[serde(rename = "reconnect")]
     Reconnect(IncomingReconnect),
     #[serde(rename = "issue_create")]
     CreateIssue(IncomingCreateIssue),
+    #[serde(rename = "issue_start")]
+    StartIssue(IncomingIssueStateChange),
+    #[serde(rename = "issue_finish")]
+    FinishIssue(IncomingIssueStateChange),
+    #[serde(rename = "issue_reset")]
+    ResetIssue(IncomingResetIssue),
+    #[serde(rename = "issue_activate")]
+    ActivateIssue(IncomingActivateIssue),
     #[serde(rename = "registration")]
     Registration(IncomingRegistration),
+    #[serde(rename = "logout")]
+    Logout,
+    #[serde(rename = "session_revoke_user")]
+    RevokeUserSessions(IncomingRevokeUserSessions),
+    #[serde(rename = "delegation_grant")]
+    GrantDelegation(IncomingGrantDelegation),
+    #[serde(rename = "delegation_revoke")]
+    RevokeDelegation(IncomingRevokeDelegation),
+    #[serde(rename = "user_set_weight")]
+    SetVoteWeight(IncomingSetVoteWeight),
+    #[serde(rename = "user_set_password")]
+    SetPassword(IncomingSetPassword),
+    #[serde(rename = "meeting_create")]
+    CreateMeeting(IncomingCreateMeeting),
+    #[serde(rename = "meeting_join")]
+    JoinMeeting(IncomingJoinMeeting),
+    #[serde(rename = "meeting_voter_add")]
+    AddMeetingVoter(IncomingMeetingVoter),
+    #[serde(rename = "meeting_voter_remove")]
+    RemoveMeetingVoter(IncomingMeetingVoter),
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub enum IssueState {
     #[serde(rename = "notstarted")]
     NotStarted,
@@ -79,15 +254,210 @@ pub struct Alternative {
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct OutgoingVote {
-    id: VoteId,
-    pub alternative_id: AlternativeId,
-    pub user_id: UserId,
+    /// Secret ballots have no id until their issue finishes
+    pub id: Option<VoteId>,
+    /// Abstentions have no alternative
+    pub alternative_id: Option<AlternativeId>,
+    /// Votes on secret issues are anonymous
+    pub user_id: Option<UserId>,
+    /// Proxy who voted for the user
+    #[serde(default)]
+    pub cast_by: Option<UserId>,
+    /// Secret ballots don't carry the weight of their voter
+    #[serde(default)]
+    pub weight: Option<i32>,
+}
+
+impl From<InternalVote> for OutgoingVote {
+    fn from(vote: InternalVote) -> Self {
+        OutgoingVote {
+            id: vote.id,
+            alternative_id: vote.alternative_id,
+            user_id: vote.user_id,
+            cast_by: vote.cast_by,
+            weight: vote.weight,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct OutgoingClient {
     pub id: SessionId,
     pub username: Option<String>,
+    pub request_id: Option<RequestId>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UserRole {
+    Admin,
+    Chair,
+    Voter,
+    Observer,
+}
+
+impl From<InternalUserRole> for UserRole {
+    fn from(role: InternalUserRole) -> Self {
+        match role {
+            InternalUserRole::Admin => UserRole::Admin,
+            InternalUserRole::Chair => UserRole::Chair,
+            InternalUserRole::Voter => UserRole::Voter,
+            InternalUserRole::Observer => UserRole::Observer,
+        }
+    }
+}
+
+/// Sent when a meeting is created or joined
+#[derive(Serialize, Deserialize)]
+pub struct OutgoingMeeting {
+    pub id: MeetingId,
+    pub title: String,
+    /// Active issue of the meeting
+    pub issue: Option<Issue>,
+    /// The user's own vote on the active issue
+    pub vote: Option<OutgoingVote>,
+}
+
+/// Everything a client needs to restore its state after logging in
+#[derive(Serialize, Deserialize)]
+pub struct OutgoingSnapshot {
+    pub issue: Option<Issue>,
+    /// The user's own vote on the issue
+    pub vote: Option<OutgoingVote>,
+    pub role: UserRole,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct OutgoingAlternativeResult {
+    pub alternative_id: AlternativeId,
+    pub votes: i64,
+    /// Summed vote weight, which decides the result
+    pub weight: i64,
+}
+
+impl From<AlternativeCount> for OutgoingAlternativeResult {
+    fn from(count: AlternativeCount) -> Self {
+        OutgoingAlternativeResult {
+            alternative_id: count.alternative_id,
+            votes: count.count,
+            weight: count.weight,
+        }
+    }
+}
+
+/// Result of a finished issue
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OutgoingResult {
+    pub issue_id: IssueId,
+    pub alternatives: Vec<OutgoingAlternativeResult>,
+    pub abstentions: i64,
+    pub vote_count: i64,
+    pub max_voters: i32,
+    /// Share of the max voters who voted
+    pub turnout: f64,
+    pub abstention_weight: i64,
+    pub total_weight: i64,
+    pub max_weight: Option<i32>,
+    pub winners: Vec<AlternativeId>,
+    pub tie: bool,
+    pub outcome: Outcome,
+    /// Instant runoff rounds of ranked issues
+    pub rounds: Vec<OutgoingRound>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct OutgoingRound {
+    pub alternatives: Vec<OutgoingAlternativeResult>,
+    pub exhausted: i64,
+    pub exhausted_weight: i64,
+    pub eliminated: Vec<AlternativeId>,
+}
+
+impl From<InternalRound> for OutgoingRound {
+    fn from(round: InternalRound) -> Self {
+        OutgoingRound {
+            alternatives: round
+                .counts
+                .into_iter()
+                .map(OutgoingAlternativeResult::from)
+                .collect(),
+            exhausted: round.exhausted,
+            exhausted_weight: round.exhausted_weight,
+            eliminated: round.eliminated,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Passed,
+    Failed,
+    NoQuorum,
+}
+
+impl From<InternalOutcome> for Outcome {
+    fn from(outcome: InternalOutcome) -> Self {
+        match outcome {
+            InternalOutcome::Passed => Outcome::Passed,
+            InternalOutcome::Failed => Outcome::Failed,
+            InternalOutcome::NoQuorum => Outcome::NoQuorum,
+        }
+    }
+}
+
+impl From<InternalResult> for OutgoingResult {
+    fn from(result: InternalResult) -> Self {
+        let turnout = if result.max_voters > 0 {
+            result.vote_count as f64 / f64::from(result.max_voters)
+        } else {
+            0.0
+        };
+        OutgoingResult {
+            issue_id: result.issue_id,
+            alternatives: result
+                .counts
+                .into_iter()
+                .map(OutgoingAlternativeResult::from)
+                .collect(),
+            abstentions: result.abstentions,
+            vote_count: result.vote_count,
+            max_voters: result.max_voters,
+            turnout,
+            abstention_weight: result.abstention_weight,
+            total_weight: result.total_weight,
+            max_weight: result.max_weight,
+            tie: result.winners.len() > 1,
+            winners: result.winners,
+            outcome: result.outcome.into(),
+            rounds: result.rounds.into_iter().map(OutgoingRound::from).collect(),
+        }
+    }
+}
+
+/// Sent instead of votes while the distribution of an issue is hidden
+#[derive(Serialize, Deserialize)]
+pub struct OutgoingVoteCount {
+    pub issue_id: IssueId,
+    pub vote_count: i64,
+    pub max_voters: i32,
+    pub total_weight: i64,
+    pub max_weight: Option<i32>,
+}
+
+/// Sent to the creator of an issue and to the clients in its meeting
+#[derive(Serialize, Deserialize)]
+pub struct OutgoingIssueCreated {
+    #[serde(flatten)]
+    pub issue: Issue,
+    /// Only set in the reply to the creator
+    pub request_id: Option<RequestId>,
+}
+
+/// Confirms that a request without any other reply was handled
+#[derive(Serialize, Deserialize)]
+pub struct OutgoingAck {
+    pub request_id: Option<RequestId>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -97,9 +467,418 @@ pub struct Issue {
     pub description: String,
     pub state: Option<IssueState>,
     pub alternatives: Vec<Alternative>,
+    /// Left out while the distribution is hidden from the client
     pub votes: Option<Vec<OutgoingVote>>,
+    #[serde(default)]
+    pub vote_count: Option<usize>,
+    pub max_voters: Option<i32>,
+    pub show_distribution: bool,
+    /// Voters may replace their vote while the issue is in progress
+    #[serde(default)]
+    pub allow_vote_change: bool,
+    /// Votes are anonymous
+    #[serde(default)]
+    pub secret: bool,
+    #[serde(default)]
+    pub voting_method: VotingMethod,
+    /// Number of alternatives selected on approval ballots, at least one by default
+    #[serde(default)]
+    pub min_choices: Option<i32>,
+    /// No maximum allows selecting all alternatives
+    #[serde(default)]
+    pub max_choices: Option<i32>,
+    #[serde(default)]
+    pub rules: DecisionRules,
+    /// Cap on the summed vote weight, the issue finishes when it is reached
+    #[serde(default)]
+    pub max_weight: Option<i32>,
+    /// Issues without a meeting are shown to clients which haven't joined one
+    #[serde(default)]
+    pub meeting_id: Option<MeetingId>,
+}
+
+/// Issue to create, it always starts out not started
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IncomingIssue {
+    pub title: String,
+    pub description: String,
+    pub alternatives: Vec<Alternative>,
+    /// No limit allows everybody who may vote, the voter roll for issues of a meeting
+    #[serde(default)]
     pub max_voters: Option<i32>,
     pub show_distribution: bool,
+    /// Voters may replace their vote while the issue is in progress
+    #[serde(default)]
+    pub allow_vote_change: bool,
+    /// Votes are anonymous
+    #[serde(default)]
+    pub secret: bool,
+    #[serde(default)]
+    pub voting_method: VotingMethod,
+    /// Number of alternatives selected on approval ballots, at least one by default
+    #[serde(default)]
+    pub min_choices: Option<i32>,
+    /// No maximum allows selecting all alternatives
+    #[serde(default)]
+    pub max_choices: Option<i32>,
+    #[serde(default)]
+    pub rules: DecisionRules,
+    /// Cap on the summed vote weight, the issue finishes when it is reached
+    #[serde(default)]
+    pub max_weight: Option<i32>,
+    /// Issues without a meeting are shown to clients which haven't joined one
+    #[serde(default)]
+    pub meeting_id: Option<MeetingId>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum VotingMethod {
+    Plurality,
+    Ranked,
+    Approval,
+}
+
+impl Default for VotingMethod {
+    fn default() -> Self {
+        VotingMethod::Plurality
+    }
+}
+
+impl From<InternalVotingMethod> for VotingMethod {
+    fn from(method: InternalVotingMethod) -> Self {
+        match method {
+            InternalVotingMethod::Plurality => VotingMethod::Plurality,
+            InternalVotingMethod::Ranked => VotingMethod::Ranked,
+            InternalVotingMethod::Approval => VotingMethod::Approval,
+        }
+    }
+}
+
+impl From<VotingMethod> for InternalVotingMethod {
+    fn from(method: VotingMethod) -> Self {
+        match method {
+            VotingMethod::Plurality => InternalVotingMethod::Plurality,
+            VotingMethod::Ranked => InternalVotingMethod::Ranked,
+            VotingMethod::Approval => InternalVotingMethod::Approval,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Majority {
+    Simple,
+    Absolute,
+    Qualified { numerator: i32, denominator: i32 },
+}
+
+impl Default for Majority {
+    fn default() -> Self {
+        Majority::Simple
+    }
+}
+
+/// How the outcome of an issue is decided when it finishes
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct DecisionRules {
+    #[serde(default)]
+    pub majority: Majority,
+    /// Percentage of max voters which has to vote, abstentions included
+    #[serde(default)]
+    pub quorum_percent: i32,
+    /// Abstentions count as votes cast for the majority
+    #[serde(default)]
+    pub count_abstentions: bool,
+}
+
+impl From<InternalDecisionRules> for DecisionRules {
+    fn from(rules: InternalDecisionRules) -> Self {
+        DecisionRules {
+            majority: match rules.majority {
+                InternalMajority::Simple => Majority::Simple,
+                InternalMajority::Absolute => Majority::Absolute,
+                InternalMajority::Qualified {
+                    numerator,
+                    denominator,
+                } => Majority::Qualified {
+                    numerator,
+                    denominator,
+                },
+            },
+            quorum_percent: rules.quorum_percent,
+            count_abstentions: rules.count_abstentions,
+        }
+    }
+}
+
+impl From<DecisionRules> for InternalDecisionRules {
+    fn from(rules: DecisionRules) -> Self {
+        InternalDecisionRules {
+            majority: match rules.majority {
+                Majority::Simple => InternalMajority::Simple,
+                Majority::Absolute => InternalMajority::Absolute,
+                Majority::Qualified {
+                    numerator,
+                    denominator,
+                } => InternalMajority::Qualified {
+                    numerator,
+                    denominator,
+                },
+            },
+            quorum_percent: rules.quorum_percent,
+            count_abstentions: rules.count_abstentions,
+        }
+    }
+}
+
+impl From<issue::InternalIssue> for Issue {
+    fn from(issue: issue::InternalIssue) -> Self {
+        let vote_count = issue.votes.len();
+        Issue {
+            id: Some(issue.id),
+            title: issue.title,
+            description: issue.description,
+            state: match issue.state {
+                db::issue::InternalIssueState::NotStarted => Some(IssueState::NotStarted),
+                db::issue::InternalIssueState::InProgress => Some(IssueState::InProgress),
+                db::issue::InternalIssueState::Finished => Some(IssueState::Finished),
+            },
+            alternatives: issue
+                .alternatives
+                .into_iter()
+                .map(|alt: db::alternative::InternalAlternative| Alternative {
+                    id: Some(alt.id),
+                    title: alt.title,
+                })
+                .collect(),
+            votes: Some(
+                issue
+                    .votes
+                    .into_iter()
+                    .map(|vote: InternalVote| vote.into())
+                    .collect(),
+            ),
+            vote_count: Some(vote_count),
+            max_voters: Some(issue.max_voters),
+            show_distribution: issue.show_distribution,
+            allow_vote_change: issue.allow_vote_change,
+            secret: issue.secret,
+            voting_method: issue.voting_method.into(),
+            min_choices: Some(issue.min_choices),
+            max_choices: issue.max_choices,
+            rules: issue.rules.into(),
+            max_weight: issue.max_weight,
+            meeting_id: issue.meeting_id,
+        }
+    }
+}
+
+/// Privileged actions which require the client to be logged in with a role allowing them
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    CreateIssue,
+    ManageIssue,
+    ManageUsers,
+    ManageMeetings,
+    /// Join meetings without being on their voter roll
+    JoinAnyMeeting,
+    Vote,
+    /// Vote for another user in demo mode
+    DemoVote,
+}
+
+impl Action {
+    fn allowed_for(self, role: &InternalUserRole) -> bool {
+        use InternalUserRole::*;
+        match self {
+            Action::CreateIssue => matches!(role, Admin),
+            Action::ManageIssue => matches!(role, Admin | Chair),
+            Action::ManageUsers => matches!(role, Admin),
+            Action::ManageMeetings => matches!(role, Admin),
+            Action::JoinAnyMeeting => matches!(role, Admin | Chair),
+            Action::Vote => matches!(role, Admin | Chair | Voter),
+            Action::DemoVote => matches!(role, Admin),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PermissionDenied(pub Action);
+
+impl fmt::Display for PermissionDenied {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = match self.0 {
+            Action::CreateIssue => "create issues",
+            Action::ManageIssue => "manage issues",
+            Action::ManageUsers => "manage users",
+            Action::ManageMeetings => "manage meetings",
+            Action::JoinAnyMeeting => "join meetings without being on their voter roll",
+            Action::Vote => "vote",
+            Action::DemoVote => "vote for other users",
+        };
+        write!(f, "Not allowed to {}", action)
+    }
+}
+
+impl std::error::Error for PermissionDenied {}
+
+#[derive(Debug)]
+pub struct NotLoggedIn;
+
+impl fmt::Display for NotLoggedIn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Not logged in")
+    }
+}
+
+impl std::error::Error for NotLoggedIn {}
+
+#[derive(Debug)]
+pub struct DemoModeDisabled;
+
+impl fmt::Display for DemoModeDisabled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Voting for other users requires demo mode")
+    }
+}
+
+impl std::error::Error for DemoModeDisabled {}
+
+#[derive(Debug)]
+pub struct InvalidSession;
+
+impl fmt::Display for InvalidSession {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Session is unknown or has expired")
+    }
+}
+
+impl std::error::Error for InvalidSession {}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    BadRequest,
+    NotLoggedIn,
+    InvalidSession,
+    PermissionDenied,
+    DemoModeDisabled,
+    IssueNotFound,
+    MeetingNotFound,
+    InvalidTransition,
+    ConfirmationRequired,
+    IssueNotInProgress,
+    AlreadyVoted,
+    NotVoted,
+    VoteChangeNotAllowed,
+    NotDelegated,
+    NotEligible,
+    NoEligibleVoters,
+    InvalidDelegation,
+    AlreadyDelegated,
+    DelegationNotFound,
+    MaxVotersReached,
+    MaxWeightReached,
+    InvalidBallot,
+    InvalidCredentials,
+    InvalidUsername,
+    InvalidPassword,
+    UsernameTaken,
+    InvalidRules,
+    InvalidWeight,
+    UserNotFound,
+    Internal,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OutgoingError {
+    pub code: ErrorCode,
+    pub message: String,
+    pub request_id: Option<RequestId>,
+}
+
+impl OutgoingError {
+    /// Maps errors that are the client's fault to an error code.
+    /// Anything unknown is reported as an internal error without details.
+    pub fn from_report(report: &Report, request_id: Option<RequestId>) -> Self {
+        let code = if report.downcast_ref::<serde_json::Error>().is_some() {
+            ErrorCode::BadRequest
+        } else if report.downcast_ref::<NotLoggedIn>().is_some() {
+            ErrorCode::NotLoggedIn
+        } else if report.downcast_ref::<InvalidSession>().is_some() {
+            ErrorCode::InvalidSession
+        } else if report.downcast_ref::<PermissionDenied>().is_some() {
+            ErrorCode::PermissionDenied
+        } else if report.downcast_ref::<DemoModeDisabled>().is_some() {
+            ErrorCode::DemoModeDisabled
+        } else if let Some(err) = report.downcast_ref::<IssueError>() {
+            match err {
+                IssueError::NotFound(_) => ErrorCode::IssueNotFound,
+                IssueError::InvalidTransition(_, _) => ErrorCode::InvalidTransition,
+                IssueError::InvalidRules(_) => ErrorCode::InvalidRules,
+                IssueError::NoEligibleVoters => ErrorCode::NoEligibleVoters,
+                IssueError::ResetNotConfirmed(_) => ErrorCode::ConfirmationRequired,
+            }
+        } else if let Some(err) = report.downcast_ref::<MeetingError>() {
+            match err {
+                MeetingError::NotFound(_) => ErrorCode::MeetingNotFound,
+                MeetingError::UnknownUser(_) => ErrorCode::UserNotFound,
+            }
+        } else if let Some(err) = report.downcast_ref::<VoteError>() {
+            match err {
+                VoteError::IssueNotInProgress(_) => ErrorCode::IssueNotInProgress,
+                VoteError::AlreadyVoted => ErrorCode::AlreadyVoted,
+                VoteError::NotVoted => ErrorCode::NotVoted,
+                VoteError::ChangeNotAllowed => ErrorCode::VoteChangeNotAllowed,
+                VoteError::NotDelegated => ErrorCode::NotDelegated,
+                VoteError::NotEligible => ErrorCode::NotEligible,
+                VoteError::MaxVotersReached(_) => ErrorCode::MaxVotersReached,
+                VoteError::MaxWeightReached(_) => ErrorCode::MaxWeightReached,
+                VoteError::InvalidBallot(_) => ErrorCode::InvalidBallot,
+            }
+        } else if let Some(err) = report.downcast_ref::<LoginError>() {
+            match err {
+                LoginError::InvalidCredentials => ErrorCode::InvalidCredentials,
+            }
+        } else if let Some(err) = report.downcast_ref::<RegistrationError>() {
+            match err {
+                RegistrationError::InvalidUsername(_) => ErrorCode::InvalidUsername,
+                RegistrationError::InvalidPassword(_) => ErrorCode::InvalidPassword,
+                RegistrationError::UsernameTaken(_) => ErrorCode::UsernameTaken,
+            }
+        } else if let Some(err) = report.downcast_ref::<VoteWeightError>() {
+            match err {
+                VoteWeightError::NotPositive(_) => ErrorCode::InvalidWeight,
+                VoteWeightError::UserNotFound(_) => ErrorCode::UserNotFound,
+            }
+        } else if let Some(err) = report.downcast_ref::<PasswordError>() {
+            match err {
+                PasswordError::UserNotFound(_) => ErrorCode::UserNotFound,
+            }
+        } else if let Some(err) = report.downcast_ref::<DelegationError>() {
+            match err {
+                DelegationError::SelfDelegation
+                | DelegationError::IssueAndMeeting
+                | DelegationError::UnknownUserOrIssue => ErrorCode::InvalidDelegation,
+                DelegationError::AlreadyDelegated => ErrorCode::AlreadyDelegated,
+                DelegationError::NotFound => ErrorCode::DelegationNotFound,
+            }
+        } else {
+            ErrorCode::Internal
+        };
+        let message = match code {
+            ErrorCode::Internal => "Internal server error".to_string(),
+            // Root cause skips context such as "JSON decode"
+            _ => report.root_cause().to_string(),
+        };
+        Self {
+            code,
+            message,
+            request_id,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -107,24 +886,90 @@ pub struct Issue {
 pub enum OutgoingMessage {
     #[serde(rename = "issue")]
     Issue(Issue),
+    /// An issue was added without replacing the issue the client is shown
+    #[serde(rename = "issue_created")]
+    IssueCreated(OutgoingIssueCreated),
     #[serde(rename = "vote")]
     Vote(OutgoingVote),
+    /// A user replaced their earlier vote on the issue
+    #[serde(rename = "vote_changed")]
+    VoteChanged(OutgoingVote),
+    #[serde(rename = "vote_retracted")]
+    VoteRetracted(OutgoingVote),
+    #[serde(rename = "vote_count")]
+    VoteCount(OutgoingVoteCount),
+    #[serde(rename = "result")]
+    Result(OutgoingResult),
     #[serde(rename = "client")]
     Client(OutgoingClient),
+    #[serde(rename = "error")]
+    Error(OutgoingError),
+    #[serde(rename = "snapshot")]
+    Snapshot(OutgoingSnapshot),
+    #[serde(rename = "meeting")]
+    Meeting(OutgoingMeeting),
+    #[serde(rename = "ack")]
+    Ack(OutgoingAck),
+    /// The session of this client was revoked and it is logged out
+    #[serde(rename = "session_revoked")]
+    SessionRevoked,
+    /// The session of this client expired while it was connected and it is logged out
+    #[serde(rename = "session_expired")]
+    SessionExpired,
+    /// The user was removed from the voter roll, the client is back in the lobby
+    #[serde(rename = "meeting_left")]
+    MeetingLeft,
 }
 
 pub struct WsClient {
     session_id: Option<SessionId>,
     user_id: Option<UserId>,
+    role: Option<InternalUserRole>,
+    /// Issues and broadcasts are those of the joined meeting
+    meeting_id: Option<MeetingId>,
+    settings: Settings,
 }
 
 impl WsClient {
-    pub fn new() -> Self {
+    pub fn new(settings: Settings) -> Self {
         Self {
             session_id: None,
             user_id: None,
+            role: None,
+            meeting_id: None,
+            settings,
+        }
+    }
+
+    /// Votes are only shown before an issue is finished if it shows its distribution.
+    /// Admins always see all votes.
+    fn can_see_votes(&self, show_distribution: bool, state: &InternalIssueState) -> bool {
+        show_distribution
+            || *state == InternalIssueState::Finished
+            || self.role == Some(InternalUserRole::Admin)
+    }
+
+    fn issue_for_client(&self, issue: issue::InternalIssue) -> Issue {
+        let visible = self.can_see_votes(issue.show_distribution, &issue.state);
+        let mut issue = Issue::from(issue);
+        if !visible {
+            issue.votes = None;
+        }
+        issue
+    }
+
+    fn clear_session(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        self.session_id = None;
+        self.user_id = None;
+        self.role = None;
+        // Only logged in users attend meetings
+        if self.meeting_id.take().is_some() {
+            BroadcastActor::from_registry().do_send(LeaveMeeting {
+                addr: ctx.address(),
+            });
         }
     }
+
     fn send_json<T: Serialize>(
         &self,
         ctx: &mut ws::WebsocketContext<Self>,
@@ -138,7 +983,7 @@ impl WsClient {
 
 impl Default for WsClient {
     fn default() -> Self {
-        Self::new()
+        Self::new(Settings::default())
     }
 }
 
@@ -146,31 +991,184 @@ fn report_error(report: Report) {
     error!("Error report: {:?}", report);
 }
 
+/// Sends an error message back to the client which sent the failing request
+fn reply_error(report: Report, request_id: Option<RequestId>) {
+    let error = OutgoingError::from_report(&report, request_id);
+    if error.code == ErrorCode::Internal {
+        report_error(report);
+    } else {
+        warn!("Client error: {}", report);
+    }
+    let res =
+        with_ctx(|act: &mut WsClient, ctx| act.send_json(ctx, &OutgoingMessage::Error(error)));
+    if let Err(err) = res {
+        report_error(err);
+    }
+}
+
+/// Returns the logged in user if their role allows them to perform the action
+fn require_permission(action: Action) -> Result<UserId, Report> {
+    with_ctx(|act: &mut WsClient, _| match (&act.user_id, &act.role) {
+        (Some(user_id), Some(role)) if action.allowed_for(role) => Ok(user_id.clone()),
+        _ => Err(PermissionDenied(action).into()),
+    })
+}
+
 async fn handle_vote(vote: IncomingVote) -> Result<(), Report> {
     let span = span!(Level::DEBUG, "vote", alternative_id = ?vote.alternative_id);
     let _enter = span.enter();
     debug!("Incoming vote");
     let vote_actor = VoteActor::from_registry();
-    let user_id = if let Some(user_id) = with_ctx(|act: &mut WsClient, _| act.user_id.clone()) {
-        user_id
-    } else if let Some(user_id) = vote.user_id {
-        // Fake user vote from frontend
-        user_id
-    } else {
-        return Err(eyre!("Tried to vote before logging in"));
+    let (logged_in, demo_mode) =
+        with_ctx(|act: &mut WsClient, _| (act.user_id.is_some(), act.settings.demo_mode));
+    if !logged_in {
+        return Err(NotLoggedIn.into());
+    }
+    let (user_id, demo_admin) = match vote.user_id {
+        Some(user_id) => {
+            if !demo_mode {
+                return Err(DemoModeDisabled.into());
+            }
+            let admin_id = require_permission(Action::DemoVote)?;
+            // Demo votes are only cast for users who may vote themselves
+            let target = DbExecutor::from_registry()
+                .send(SpanMessage::new(UserById(user_id.clone())))
+                .await??;
+            if !target.map_or(false, |target| Action::Vote.allowed_for(&target.role)) {
+                return Err(VoteError::NotEligible.into());
+            }
+            (user_id, Some(admin_id))
+        }
+        None => (require_permission(Action::Vote)?, None),
+    };
+    let ballot = match (vote.alternative_id, vote.alternative_ids) {
+        (Some(alternative_id), alternative_ids) if alternative_ids.is_empty() => {
+            InternalBallot::Single(Some(alternative_id))
+        }
+        (None, alternative_ids) if !alternative_ids.is_empty() => {
+            InternalBallot::Approval(alternative_ids)
+        }
+        _ => {
+            return Err(VoteError::InvalidBallot(
+                "vote needs either alternative_id or alternative_ids",
+            )
+            .into())
+        }
     };
-    let alternative_id = vote.alternative_id;
     vote_actor
-        .send(SpanMessage::new(IncomingVoteMessage(
+        .send(SpanMessage::new(IncomingVoteMessage(AddVote {
+            voter_id: user_id.clone(),
+            issue_id: vote.issue_id,
+            ballot,
+            on_behalf_of: vote.on_behalf_of,
+            demo_admin: demo_admin.clone(),
+        })))
+        .await
+        .wrap_err("Error handling incoming vote")??;
+
+    if let Some(admin_id) = demo_admin {
+        warn!(
+            admin_id = admin_id.as_string().as_str(),
+            user_id = user_id.as_string().as_str(),
+            "Demo vote cast for another user"
+        );
+    }
+    Ok(())
+}
+
+async fn handle_ranked_vote(
+    IncomingRankedVote {
+        issue_id,
+        ranking,
+        on_behalf_of,
+    }: IncomingRankedVote,
+) -> Result<(), Report> {
+    let span = span!(Level::DEBUG, "vote_ranked", issue_id = ?issue_id);
+    let _enter = span.enter();
+    debug!("Incoming ranked vote");
+    let user_id = require_permission(Action::Vote)?;
+    VoteActor::from_registry()
+        .send(SpanMessage::new(IncomingVoteMessage(AddVote {
+            voter_id: user_id,
+            issue_id,
+            ballot: InternalBallot::Ranked(ranking),
+            on_behalf_of,
+            demo_admin: None,
+        })))
+        .await
+        .wrap_err("Error handling ranked vote")?
+}
+
+async fn handle_abstain(
+    IncomingAbstain {
+        issue_id,
+        on_behalf_of,
+    }: IncomingAbstain,
+) -> Result<(), Report> {
+    let span = span!(Level::DEBUG, "abstain", issue_id = ?issue_id);
+    let _enter = span.enter();
+    debug!("Incoming abstain");
+    let user_id = require_permission(Action::Vote)?;
+    VoteActor::from_registry()
+        .send(SpanMessage::new(IncomingVoteMessage(AddVote {
+            voter_id: user_id,
+            issue_id,
+            ballot: InternalBallot::Single(None),
+            on_behalf_of,
+            demo_admin: None,
+        })))
+        .await
+        .wrap_err("Error handling abstention")?
+}
+
+async fn handle_retract_vote(
+    IncomingRetractVote {
+        issue_id,
+        on_behalf_of,
+    }: IncomingRetractVote,
+) -> Result<(), Report> {
+    let span = span!(Level::DEBUG, "vote_retract", issue_id = ?issue_id);
+    let _enter = span.enter();
+    debug!("Incoming vote retraction");
+    let user_id = require_permission(Action::Vote)?;
+    VoteActor::from_registry()
+        .send(SpanMessage::new(RetractVote(
             user_id,
-            vote.issue_id,
-            alternative_id,
+            issue_id,
+            on_behalf_of,
         )))
         .await
-        .wrap_err("Error handling incoming vote")?
+        .wrap_err("Error handling vote retraction")?
+}
+
+/// Sends the active issue and the user's own vote on it so a client can restore its state
+async fn send_snapshot(user_id: UserId, role: InternalUserRole) -> Result<(), Report> {
+    let meeting_id = with_ctx(|act: &mut WsClient, _| act.meeting_id.clone());
+    let issue = IssueService::from_registry()
+        .send(SpanMessage::new(issue::ActiveIssue(meeting_id)))
+        .await??;
+    let vote = match &issue {
+        Some(issue) => {
+            DbExecutor::from_registry()
+                .send(SpanMessage::new(VoteForUser(user_id, issue.id.clone())))
+                .await??
+        }
+        None => None,
+    };
+    with_ctx(|act: &mut WsClient, ctx| {
+        act.send_json(
+            ctx,
+            &OutgoingMessage::Snapshot(OutgoingSnapshot {
+                issue: issue.map(|issue| act.issue_for_client(issue)),
+                vote: vote.map(OutgoingVote::from),
+                role: role.into(),
+            }),
+        )
+    })
+    .wrap_err("Failed to send snapshot")
 }
 
-async fn handle_login(login: IncomingLogin) -> Result<(), Report> {
+async fn handle_login(login: IncomingLogin, request_id: Option<RequestId>) -> Result<(), Report> {
     let span = span!(Level::INFO, "login");
     let outer_span = span.clone();
     let _enter = outer_span.enter();
@@ -179,32 +1177,35 @@ async fn handle_login(login: IncomingLogin) -> Result<(), Report> {
     let res = user_actor
         .send(SpanMessage::new(Login {
             username: login.username,
+            password: login.password,
         }))
         .await;
     let user = res??;
-    if let Some(user) = user {
-        let session_actor = SessionActor::from_registry();
-        let session = session_actor
-            .send(SpanMessage::new(SaveSession(user.id.clone())))
-            .await??;
-        with_ctx(|act: &mut WsClient, ctx| {
-            act.session_id = Some(session.id.clone());
-            act.user_id = Some(user.id);
-            act.send_json(
-                ctx,
-                &OutgoingMessage::Client(OutgoingClient {
-                    id: session.id,
-                    username: Some(user.username),
-                }),
-            )
-        })
-        .wrap_err("Failed to send client message on login")?;
-    }
-    Ok(())
+    let session_actor = SessionActor::from_registry();
+    let session = session_actor
+        .send(SpanMessage::new(SaveSession(user.id.clone())))
+        .await??;
+    let (user_id, role) = (user.id.clone(), user.role.clone());
+    with_ctx(|act: &mut WsClient, ctx| {
+        act.session_id = Some(session.id.clone());
+        act.user_id = Some(user.id);
+        act.role = Some(user.role);
+        act.send_json(
+            ctx,
+            &OutgoingMessage::Client(OutgoingClient {
+                id: session.id,
+                username: Some(user.username),
+                request_id,
+            }),
+        )
+    })
+    .wrap_err("Failed to send client message on login")?;
+    send_snapshot(user_id, role).await
 }
 
 async fn handle_reconnect(
     IncomingReconnect { session_id }: IncomingReconnect,
+    request_id: Option<RequestId>,
 ) -> Result<(), Report> {
     let span = span!(
         Level::INFO,
@@ -219,60 +1220,353 @@ async fn handle_reconnect(
         .send(SpanMessage::new(SessionById(session_id.clone())))
         .await;
     let session: Option<InternalSession> = res??;
-    if let Some(session) = session {
-        info!("Found session");
-        with_ctx(|act: &mut WsClient, _| {
-            act.session_id = Some(session.id.clone());
-            act.user_id = Some(session.user_id.clone());
-        });
-        let db_executor = DbExecutor::from_registry();
-        let user = db_executor
-            .send(SpanMessage::new(UserById(session.user_id)))
-            .await;
-        if let Some(user) = user?? {
-            info!("Found user, sending client info");
-            with_ctx(|act: &mut WsClient, ctx| {
-                act.send_json(
-                    ctx,
-                    &OutgoingMessage::Client(OutgoingClient {
-                        id: session_id,
-                        username: Some(user.username),
-                    }),
-                )
-            })
-            .wrap_err("Failed to send client message on reconnect")?;
-        } else {
-            error!("Unable to find user connected to session");
-        }
+    let session = session.ok_or(InvalidSession)?;
+    info!("Found session");
+    with_ctx(|act: &mut WsClient, _| {
+        act.session_id = Some(session.id.clone());
+        act.user_id = Some(session.user_id.clone());
+    });
+    let db_executor = DbExecutor::from_registry();
+    let user = db_executor
+        .send(SpanMessage::new(UserById(session.user_id.clone())))
+        .await;
+    if let Some(user) = user?? {
+        info!("Found user, sending client info");
+        let role = user.role.clone();
+        with_ctx(|act: &mut WsClient, ctx| {
+            act.role = Some(user.role);
+            act.send_json(
+                ctx,
+                &OutgoingMessage::Client(OutgoingClient {
+                    id: session_id,
+                    username: Some(user.username),
+                    request_id,
+                }),
+            )
+        })
+        .wrap_err("Failed to send client message on reconnect")?;
+        send_snapshot(session.user_id, role).await?;
     } else {
-        warn!("Unable to find session");
+        error!("Unable to find user connected to session");
+    }
+    Ok(())
+}
+
+async fn handle_logout() -> Result<(), Report> {
+    let span = span!(Level::DEBUG, "logout");
+    let _enter = span.enter();
+    debug!("Incoming logout");
+    let session_id = with_ctx(|act: &mut WsClient, _| act.session_id.clone()).ok_or(NotLoggedIn)?;
+    SessionActor::from_registry()
+        .send(SpanMessage::new(Logout(session_id)))
+        .await??;
+    with_ctx(|act: &mut WsClient, ctx| act.clear_session(ctx));
+    info!("Logged out");
+    Ok(())
+}
+
+async fn handle_revoke_user_sessions(
+    IncomingRevokeUserSessions { user_id }: IncomingRevokeUserSessions,
+) -> Result<(), Report> {
+    let span = span!(
+        Level::DEBUG,
+        "session_revoke_user",
+        user_id = user_id.as_string().as_str()
+    );
+    let _enter = span.enter();
+    debug!("Incoming RevokeUserSessions");
+    require_permission(Action::ManageUsers)?;
+    SessionActor::from_registry()
+        .send(SpanMessage::new(RevokeUserSessions(user_id)))
+        .await?
+}
+
+async fn handle_set_vote_weight(
+    IncomingSetVoteWeight { user_id, weight }: IncomingSetVoteWeight,
+) -> Result<(), Report> {
+    let span = span!(
+        Level::DEBUG,
+        "user_set_weight",
+        user_id = user_id.as_string().as_str()
+    );
+    let _enter = span.enter();
+    debug!("Incoming SetVoteWeight");
+    require_permission(Action::ManageUsers)?;
+    DbExecutor::from_registry()
+        .send(SpanMessage::new(SetVoteWeight(user_id, weight)))
+        .await??;
+    info!(weight, "Vote weight set");
+    Ok(())
+}
+
+async fn handle_set_password(
+    IncomingSetPassword { user_id, password }: IncomingSetPassword,
+) -> Result<(), Report> {
+    let span = span!(
+        Level::DEBUG,
+        "user_set_password",
+        user_id = user_id.as_string().as_str()
+    );
+    let _enter = span.enter();
+    debug!("Incoming SetPassword");
+    require_permission(Action::ManageUsers)?;
+    ClientActor::from_registry()
+        .send(SpanMessage::new(SetPassword { user_id, password }))
+        .await??;
+    info!("Password set");
+    Ok(())
+}
+
+fn send_meeting(
+    meeting: InternalMeeting,
+    issue: Option<issue::InternalIssue>,
+    vote: Option<InternalVote>,
+) -> Result<(), Report> {
+    with_ctx(|act: &mut WsClient, ctx| {
+        act.send_json(
+            ctx,
+            &OutgoingMessage::Meeting(OutgoingMeeting {
+                id: meeting.id,
+                title: meeting.title,
+                issue: issue.map(|issue| act.issue_for_client(issue)),
+                vote: vote.map(OutgoingVote::from),
+            }),
+        )
+    })
+    .wrap_err("Failed to send meeting")
+}
+
+async fn handle_create_meeting(
+    IncomingCreateMeeting { title }: IncomingCreateMeeting,
+) -> Result<(), Report> {
+    let span = span!(Level::DEBUG, "meeting_create", title = title.as_str());
+    let _enter = span.enter();
+    debug!("Incoming CreateMeeting");
+    require_permission(Action::ManageMeetings)?;
+    let meeting = DbExecutor::from_registry()
+        .send(SpanMessage::new(NewMeeting { title }))
+        .await??;
+    info!("Meeting created");
+    send_meeting(meeting, None, None)
+}
+
+async fn handle_join_meeting(
+    IncomingJoinMeeting { meeting_id }: IncomingJoinMeeting,
+) -> Result<(), Report> {
+    let span = span!(Level::DEBUG, "meeting_join", meeting_id = ?meeting_id);
+    let _enter = span.enter();
+    debug!("Incoming JoinMeeting");
+    let user_id = with_ctx(|act: &mut WsClient, _| act.user_id.clone()).ok_or(NotLoggedIn)?;
+    let meeting = DbExecutor::from_registry()
+        .send(SpanMessage::new(MeetingById(meeting_id.clone())))
+        .await??;
+    // Admins and chairs run every meeting, other users only attend those they may vote in
+    let via_roll = match require_permission(Action::JoinAnyMeeting) {
+        Ok(_) => false,
+        Err(denied) => {
+            let on_roll = DbExecutor::from_registry()
+                .send(SpanMessage::new(OnVoterRoll(
+                    meeting_id.clone(),
+                    user_id.clone(),
+                )))
+                .await??;
+            if !on_roll {
+                return Err(denied);
+            }
+            true
+        }
+    };
+    let addr = with_ctx(|act: &mut WsClient, ctx| {
+        act.meeting_id = Some(meeting_id.clone());
+        ctx.address()
+    });
+    BroadcastActor::from_registry().do_send(JoinMeeting {
+        addr,
+        meeting_id: meeting_id.clone(),
+        user_id: user_id.clone(),
+        via_roll,
+    });
+
+    let issue = IssueService::from_registry()
+        .send(SpanMessage::new(issue::ActiveIssue(Some(meeting_id))))
+        .await??;
+    let (mut vote, mut result) = (None, None);
+    if let Some(issue) = &issue {
+        vote = DbExecutor::from_registry()
+            .send(SpanMessage::new(VoteForUser(user_id, issue.id.clone())))
+            .await??;
+        if issue.state == InternalIssueState::Finished {
+            result = DbExecutor::from_registry()
+                .send(SpanMessage::new(ResultForIssue(issue.id.clone())))
+                .await??;
+        }
     }
+    send_meeting(meeting, issue, vote)?;
+    if let Some(result) = result {
+        with_ctx(|act: &mut WsClient, ctx| {
+            act.send_json(ctx, &OutgoingMessage::Result(result.into()))
+        })
+        .wrap_err("Failed to send result")?;
+    }
+    info!("Joined meeting");
+    Ok(())
+}
+
+async fn handle_add_meeting_voter(
+    IncomingMeetingVoter {
+        meeting_id,
+        user_id,
+    }: IncomingMeetingVoter,
+) -> Result<(), Report> {
+    let span = span!(
+        Level::DEBUG,
+        "meeting_voter_add",
+        user_id = user_id.as_string().as_str()
+    );
+    let _enter = span.enter();
+    debug!("Incoming AddMeetingVoter");
+    require_permission(Action::ManageMeetings)?;
+    DbExecutor::from_registry()
+        .send(SpanMessage::new(AddVoter(meeting_id, user_id)))
+        .await??;
+    info!("Voter added to meeting");
+    Ok(())
+}
+
+async fn handle_remove_meeting_voter(
+    IncomingMeetingVoter {
+        meeting_id,
+        user_id,
+    }: IncomingMeetingVoter,
+) -> Result<(), Report> {
+    let span = span!(
+        Level::DEBUG,
+        "meeting_voter_remove",
+        user_id = user_id.as_string().as_str()
+    );
+    let _enter = span.enter();
+    debug!("Incoming RemoveMeetingVoter");
+    require_permission(Action::ManageMeetings)?;
+    DbExecutor::from_registry()
+        .send(SpanMessage::new(RemoveVoter(
+            meeting_id.clone(),
+            user_id.clone(),
+        )))
+        .await??;
+    // Clients of the voter no longer receive the meeting's broadcasts
+    BroadcastActor::from_registry().do_send(VoterRemoved(meeting_id, user_id));
+    info!("Voter removed from meeting");
+    Ok(())
+}
+
+async fn handle_grant_delegation(
+    IncomingGrantDelegation {
+        proxy_id,
+        issue_id,
+        meeting_id,
+    }: IncomingGrantDelegation,
+) -> Result<(), Report> {
+    let span = span!(
+        Level::DEBUG,
+        "delegation_grant",
+        proxy_id = proxy_id.as_string().as_str()
+    );
+    let _enter = span.enter();
+    debug!("Incoming GrantDelegation");
+    let principal_id = require_permission(Action::Vote)?;
+    DbExecutor::from_registry()
+        .send(SpanMessage::new(GrantDelegation {
+            principal_id,
+            proxy_id,
+            issue_id,
+            meeting_id,
+        }))
+        .await??;
+    info!("Delegation granted");
+    Ok(())
+}
+
+async fn handle_revoke_delegation(
+    IncomingRevokeDelegation {
+        issue_id,
+        meeting_id,
+    }: IncomingRevokeDelegation,
+) -> Result<(), Report> {
+    let span = span!(Level::DEBUG, "delegation_revoke", issue_id = ?issue_id);
+    let _enter = span.enter();
+    debug!("Incoming RevokeDelegation");
+    let principal_id = require_permission(Action::Vote)?;
+    DbExecutor::from_registry()
+        .send(SpanMessage::new(RevokeDelegation {
+            principal_id,
+            issue_id,
+            meeting_id,
+        }))
+        .await??;
+    info!("Delegation revoked");
     Ok(())
 }
 
 async fn handle_create_issue(
     IncomingCreateIssue { issue }: IncomingCreateIssue,
+    request_id: Option<RequestId>,
 ) -> Result<(), Report> {
     let span = span!(Level::DEBUG, "issue_create", issue = issue.title.as_str());
     let _enter = span.enter();
     debug!("Incoming CreateIssue");
     let issue_actor = IssueService::from_registry();
-    let _ = if let Some(user_id) = with_ctx(|act: &mut WsClient, _| act.user_id.clone()) {
-        user_id
-    } else {
-        return Err(eyre!(
-            "Not sure who the user who tried to create an issue is"
-        ));
+    require_permission(Action::CreateIssue)?;
+    let creator = with_ctx(|_: &mut WsClient, ctx| ctx.address());
+    let issue = issue_actor
+        .send(SpanMessage::new(NewIssue(issue, creator)))
+        .await
+        .wrap_err("Error handling incoming new issue")??;
+    info!("Created issue {:?}", issue.id);
+    // The creator may not be in the issue's meeting, so it isn't sent the broadcast
+    with_ctx(|act: &mut WsClient, ctx| {
+        let issue = act.issue_for_client(issue);
+        act.send_json(
+            ctx,
+            &OutgoingMessage::IssueCreated(OutgoingIssueCreated { issue, request_id }),
+        )
+    })
+    .wrap_err("Failed to send created issue")
+}
+
+async fn handle_issue_state_change(
+    issue_id: IssueId,
+    transition: IssueTransition,
+    confirmed: bool,
+) -> Result<(), Report> {
+    let span = span!(Level::DEBUG, "issue_state_change", transition = ?transition);
+    let _enter = span.enter();
+    debug!("Incoming issue state change");
+    let actor_id = require_permission(Action::ManageIssue)?;
+    let update = db::issue::UpdateIssueState {
+        issue_id,
+        transition,
+        actor_id,
+        confirmed,
     };
-    // @ToDo: Check if user is admin
-    let resp = issue_actor
-        .send(SpanMessage::new(NewIssue(issue)))
+    IssueService::from_registry()
+        .send(SpanMessage::new(UpdateIssueState(update)))
         .await
-        .wrap_err("Error handling incoming new issue")?;
-    match resp {
-        Ok(_) => Ok(()), // respond back with issue maybe?
-        _ => Ok(()),     // how to handle error hmm
-    }
+        .wrap_err("Error handling issue state change")??;
+    Ok(())
+}
+
+async fn handle_activate_issue(
+    IncomingActivateIssue { issue_id }: IncomingActivateIssue,
+) -> Result<(), Report> {
+    let span = span!(Level::DEBUG, "issue_activate", issue_id = ?issue_id);
+    let _enter = span.enter();
+    debug!("Incoming ActivateIssue");
+    require_permission(Action::ManageIssue)?;
+    IssueService::from_registry()
+        .send(SpanMessage::new(SetActiveIssue(issue_id)))
+        .await
+        .wrap_err("Error handling issue activation")??;
+    Ok(())
 }
 
 #[derive(Serialize, Deserialize)]
@@ -281,7 +1575,10 @@ struct User {
     pub username: String,
 }
 
-async fn handle_registration(registration: IncomingRegistration) -> Result<(), Report> {
+async fn handle_registration(
+    registration: IncomingRegistration,
+    request_id: Option<RequestId>,
+) -> Result<(), Report> {
     let span = span!(
         Level::DEBUG,
         "registration",
@@ -289,11 +1586,11 @@ async fn handle_registration(registration: IncomingRegistration) -> Result<(), R
     );
     let _enter = span.enter();
     debug!("Incoming UserRegistration");
-    let db_executor = DbExecutor::from_registry();
-    let user = db_executor
-        .send(SpanMessage::new(NewUser(NewInternalUser {
+    let user = ClientActor::from_registry()
+        .send(SpanMessage::new(Registration {
             username: registration.username,
-        })))
+            password: registration.password,
+        }))
         .await??;
     info!("Successfully registered user {:?}", user);
     let session_actor = SessionActor::from_registry();
@@ -303,12 +1600,14 @@ async fn handle_registration(registration: IncomingRegistration) -> Result<(), R
     debug!("Created session");
     with_ctx(|act: &mut WsClient, ctx| {
         act.user_id = Some(user.id);
+        act.role = Some(user.role);
         act.session_id = Some(session.id.clone());
         act.send_json(
             ctx,
             &OutgoingMessage::Client(OutgoingClient {
                 id: session.id,
                 username: Some(user.username),
+                request_id,
             }),
         )
     })
@@ -316,15 +1615,121 @@ async fn handle_registration(registration: IncomingRegistration) -> Result<(), R
     Ok(())
 }
 
-async fn handle_ws_message(text: String) -> Result<(), Report> {
-    let m = serde_json::from_str(&text).wrap_err("JSON decode")?;
+/// Looks for a request id in messages that couldn't be decoded
+fn request_id_from_json(text: &str) -> Option<RequestId> {
+    let value: serde_json::Value = serde_json::from_str(text).ok()?;
+    serde_json::from_value(value.get("request_id")?.clone()).ok()
+}
+
+async fn handle_ws_text(text: String) {
+    let (request_id, res) = match serde_json::from_str::<IncomingEnvelope>(&text) {
+        Ok(IncomingEnvelope {
+            request_id,
+            message,
+        }) => (
+            request_id.clone(),
+            handle_ws_message(message, request_id).await,
+        ),
+        Err(err) => (
+            request_id_from_json(&text),
+            Err(Report::new(err).wrap_err("JSON decode")),
+        ),
+    };
+    if let Err(err) = res {
+        reply_error(err, request_id);
+    }
+}
+
+/// Logs the client out if its session expired while it was connected,
+/// otherwise the session is extended as on a reconnect
+async fn refresh_session() -> Result<(), Report> {
+    let session_id = match with_ctx(|act: &mut WsClient, _| act.session_id.clone()) {
+        Some(session_id) => session_id,
+        None => return Ok(()),
+    };
+    let session = SessionActor::from_registry()
+        .send(SpanMessage::new(SessionById(session_id)))
+        .await??;
+    if session.is_none() {
+        info!("Session expired, logging out client");
+        with_ctx(|act: &mut WsClient, ctx| {
+            act.clear_session(ctx);
+            act.send_json(ctx, &OutgoingMessage::SessionExpired)
+        })?;
+        return Ok(());
+    }
+
+    // The user may have been removed from the voter roll of the joined meeting
+    let (user_id, meeting_id) =
+        match with_ctx(|act: &mut WsClient, _| (act.user_id.clone(), act.meeting_id.clone())) {
+            (Some(user_id), Some(meeting_id)) => (user_id, meeting_id),
+            _ => return Ok(()),
+        };
+    if require_permission(Action::JoinAnyMeeting).is_ok() {
+        return Ok(());
+    }
+    let on_roll = DbExecutor::from_registry()
+        .send(SpanMessage::new(OnVoterRoll(meeting_id, user_id)))
+        .await??;
+    if !on_roll {
+        info!("Voter was removed from the roll, leaving meeting");
+        with_ctx(|act: &mut WsClient, ctx| {
+            act.meeting_id = None;
+            BroadcastActor::from_registry().do_send(LeaveMeeting {
+                addr: ctx.address(),
+            });
+            act.send_json(ctx, &OutgoingMessage::MeetingLeft)
+        })?;
+    }
+    Ok(())
+}
+
+async fn handle_ws_message(
+    m: IncomingMessage,
+    request_id: Option<RequestId>,
+) -> Result<(), Report> {
+    refresh_session().await?;
     match m {
-        IncomingMessage::Vote(vote) => handle_vote(vote).await,
-        IncomingMessage::Login(login) => handle_login(login).await,
-        IncomingMessage::Reconnect(reconnect) => handle_reconnect(reconnect).await,
-        IncomingMessage::CreateIssue(issue) => handle_create_issue(issue).await,
-        IncomingMessage::Registration(registration) => handle_registration(registration).await,
+        IncomingMessage::Vote(vote) => handle_vote(vote).await?,
+        IncomingMessage::RankedVote(vote) => handle_ranked_vote(vote).await?,
+        IncomingMessage::Abstain(abstain) => handle_abstain(abstain).await?,
+        IncomingMessage::RetractVote(retract) => handle_retract_vote(retract).await?,
+        IncomingMessage::Login(login) => return handle_login(login, request_id).await,
+        IncomingMessage::Reconnect(reconnect) => {
+            return handle_reconnect(reconnect, request_id).await
+        }
+        IncomingMessage::CreateIssue(issue) => return handle_create_issue(issue, request_id).await,
+        IncomingMessage::StartIssue(change) => {
+            handle_issue_state_change(change.issue_id, IssueTransition::Start, false).await?
+        }
+        IncomingMessage::FinishIssue(change) => {
+            handle_issue_state_change(change.issue_id, IssueTransition::Finish, false).await?
+        }
+        IncomingMessage::ResetIssue(reset) => {
+            handle_issue_state_change(reset.issue_id, IssueTransition::Reset, reset.confirm).await?
+        }
+        IncomingMessage::ActivateIssue(activate) => handle_activate_issue(activate).await?,
+        IncomingMessage::Registration(registration) => {
+            return handle_registration(registration, request_id).await
+        }
+        IncomingMessage::Logout => handle_logout().await?,
+        IncomingMessage::RevokeUserSessions(revoke) => handle_revoke_user_sessions(revoke).await?,
+        IncomingMessage::GrantDelegation(grant) => handle_grant_delegation(grant).await?,
+        IncomingMessage::RevokeDelegation(revoke) => handle_revoke_delegation(revoke).await?,
+        IncomingMessage::SetVoteWeight(set_weight) => handle_set_vote_weight(set_weight).await?,
+        IncomingMessage::SetPassword(set_password) => handle_set_password(set_password).await?,
+        IncomingMessage::CreateMeeting(meeting) => handle_create_meeting(meeting).await?,
+        IncomingMessage::JoinMeeting(join) => handle_join_meeting(join).await?,
+        IncomingMessage::AddMeetingVoter(voter) => handle_add_meeting_voter(voter).await?,
+        IncomingMessage::RemoveMeetingVoter(voter) => handle_remove_meeting_voter(voter).await?,
+    }
+    // Only clients asking for correlation care about acks
+    if request_id.is_some() {
+        with_ctx(|act: &mut WsClient, ctx| {
+            act.send_json(ctx, &OutgoingMessage::Ack(OutgoingAck { request_id }))
+        })?;
     }
+    Ok(())
 }
 
 impl Actor for WsClient {
@@ -356,14 +1761,7 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsClient {
         match msg {
             Ok(message) => match message {
                 ws::Message::Text(text) => {
-                    ctx.spawn(
-                        async move {
-                            if let Err(err) = handle_ws_message(text).await {
-                                report_error(err);
-                            }
-                        }
-                        .interop_actor_boxed(self),
-                    );
+                    ctx.spawn(handle_ws_text(text).interop_actor_boxed(self));
                 }
                 ws::Message::Close(reason) => {
                     debug!("Got close message from WS. Reason: {:#?}", reason);
@@ -386,39 +1784,38 @@ impl Handler<services::ActiveIssue> for WsClient {
 
     fn handle(&mut self, msg: services::ActiveIssue, ctx: &mut Self::Context) {
         debug!("Handling ActiveIssue event");
-        let issue = msg.0;
+        let issue = self.issue_for_client(msg.0);
+        let res = self.send_json(ctx, &OutgoingMessage::Issue(issue));
+        if let Err(err) = res {
+            report_error(err);
+        }
+    }
+}
+
+impl Handler<BroadcastIssue> for WsClient {
+    type Result = ();
+
+    fn handle(&mut self, msg: BroadcastIssue, ctx: &mut Self::Context) {
+        debug!("Handling BroadcastIssue event");
+        let issue = self.issue_for_client(msg.0);
+        let res = self.send_json(ctx, &OutgoingMessage::Issue(issue));
+        if let Err(err) = res {
+            report_error(err);
+        }
+    }
+}
+
+impl Handler<BroadcastIssueCreated> for WsClient {
+    type Result = ();
+
+    fn handle(&mut self, msg: BroadcastIssueCreated, ctx: &mut Self::Context) {
+        debug!("Handling BroadcastIssueCreated event");
+        let issue = self.issue_for_client(msg.0);
         let res = self.send_json(
             ctx,
-            &OutgoingMessage::Issue(Issue {
-                id: Some(issue.id),
-                title: issue.title,
-                description: issue.description,
-                state: match issue.state {
-                    db::issue::InternalIssueState::NotStarted => Some(IssueState::NotStarted),
-                    db::issue::InternalIssueState::InProgress => Some(IssueState::InProgress),
-                    db::issue::InternalIssueState::Finished => Some(IssueState::Finished),
-                },
-                alternatives: issue
-                    .alternatives
-                    .into_iter()
-                    .map(|alt: db::alternative::InternalAlternative| Alternative {
-                        id: Some(alt.id),
-                        title: alt.title,
-                    })
-                    .collect(),
-                votes: Some(
-                    issue
-                        .votes
-                        .into_iter()
-                        .map(|vote: InternalVote| OutgoingVote {
-                            id: vote.id,
-                            alternative_id: vote.alternative_id,
-                            user_id: vote.user_id,
-                        })
-                        .collect(),
-                ),
-                max_voters: Some(issue.max_voters),
-                show_distribution: issue.show_distribution,
+            &OutgoingMessage::IssueCreated(OutgoingIssueCreated {
+                issue,
+                request_id: None,
             }),
         );
         if let Err(err) = res {
@@ -427,19 +1824,75 @@ impl Handler<services::ActiveIssue> for WsClient {
     }
 }
 
+impl Handler<BroadcastResult> for WsClient {
+    type Result = ();
+
+    fn handle(&mut self, msg: BroadcastResult, ctx: &mut Self::Context) {
+        debug!("Handling BroadcastResult event");
+        let res = self.send_json(ctx, &OutgoingMessage::Result(msg.0.into()));
+        if let Err(err) = res {
+            report_error(err);
+        }
+    }
+}
+
+impl Handler<BroadcastSessionsRevoked> for WsClient {
+    type Result = ();
+
+    fn handle(&mut self, msg: BroadcastSessionsRevoked, ctx: &mut Self::Context) {
+        if self.user_id.as_ref() != Some(&msg.0) {
+            return;
+        }
+        debug!("Session revoked, logging out client");
+        self.clear_session(ctx);
+        if let Err(err) = self.send_json(ctx, &OutgoingMessage::SessionRevoked) {
+            report_error(err);
+        }
+    }
+}
+
+impl Handler<LeftMeeting> for WsClient {
+    type Result = ();
+
+    fn handle(&mut self, msg: LeftMeeting, ctx: &mut Self::Context) {
+        if self.meeting_id.as_ref() != Some(&msg.0) {
+            return;
+        }
+        debug!("Removed from the voter roll, leaving meeting");
+        self.meeting_id = None;
+        if let Err(err) = self.send_json(ctx, &OutgoingMessage::MeetingLeft) {
+            report_error(err);
+        }
+    }
+}
+
 impl Handler<BroadcastVote> for WsClient {
     type Result = ();
 
     fn handle(&mut self, msg: BroadcastVote, ctx: &mut Self::Context) {
-        let vote = msg.0;
-        let res = self.send_json(
-            ctx,
-            &OutgoingMessage::Vote(OutgoingVote {
-                id: vote.id,
-                alternative_id: vote.alternative_id,
-                user_id: vote.user_id,
-            }),
-        );
+        let BroadcastVote(change, tally) = msg;
+        // Votes are only broadcast while the issue is in progress
+        let visible = self.can_see_votes(tally.show_distribution, &InternalIssueState::InProgress);
+        // Voters always learn about their own votes
+        let vote = change.vote();
+        let own_vote = (vote.user_id.is_some() && vote.user_id == self.user_id)
+            || (vote.cast_by.is_some() && vote.cast_by == self.user_id);
+        let message = if !visible && !own_vote {
+            OutgoingMessage::VoteCount(OutgoingVoteCount {
+                issue_id: tally.issue_id,
+                vote_count: tally.vote_count,
+                max_voters: tally.max_voters,
+                total_weight: tally.total_weight,
+                max_weight: tally.max_weight,
+            })
+        } else {
+            match change {
+                VoteChange::Added(vote) => OutgoingMessage::Vote(vote.into()),
+                VoteChange::Replaced(vote) => OutgoingMessage::VoteChanged(vote.into()),
+                VoteChange::Retracted(vote) => OutgoingMessage::VoteRetracted(vote.into()),
+            }
+        };
+        let res = self.send_json(ctx, &message);
         if let Err(err) = res {
             report_error(err);
         }
@@ -5,13 +5,25 @@ use actix_web::{test, App};
 use actix_web_actors::ws;
 use futures::{SinkExt, StreamExt};
 use insta::assert_ron_snapshot;
+use sqlx::{types::Uuid, PgPool};
 use std::env;
 use std::sync::Once;
 use std::time::Duration;
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::time::timeout;
-use vaas_server::{server, websocket};
-use websocket::{IncomingLogin, IncomingMessage, IncomingReconnect, IncomingVote, OutgoingMessage};
+use vaas_server::{
+    db::{alternative::AlternativeId, issue::IssueId, meeting::MeetingId, user::UserId},
+    server, websocket,
+};
+use websocket::{
+    Alternative, ErrorCode, IncomingAbstain, IncomingActivateIssue, IncomingCreateIssue,
+    IncomingCreateMeeting, IncomingEnvelope, IncomingGrantDelegation, IncomingIssue,
+    IncomingIssueStateChange, IncomingJoinMeeting, IncomingLogin, IncomingMeetingVoter,
+    IncomingMessage, IncomingRankedVote, IncomingReconnect, IncomingRegistration,
+    IncomingResetIssue, IncomingRetractVote, IncomingRevokeDelegation, IncomingRevokeUserSessions,
+    IncomingSetPassword, IncomingSetVoteWeight, IncomingVote, Issue, IssueState, Majority, Outcome,
+    OutgoingClient, OutgoingMessage, RequestId, UserRole, VotingMethod,
+};
 
 mod integration_db;
 use integration_db::IntegrationTestDb;
@@ -63,15 +75,78 @@ async fn read_message(
 }
 
 async fn read_messages(
-    mut framed: &mut Framed<impl AsyncRead + AsyncWrite, Codec>,
+    framed: &mut Framed<impl AsyncRead + AsyncWrite, Codec>,
 ) -> Vec<OutgoingMessage> {
     let mut messages = vec![];
-    while let Some(message) = read_message(&mut framed).await {
+    while let Some(message) = read_message(framed).await {
         messages.push(message);
     }
     messages
 }
 
+async fn send_message(
+    framed: &mut Framed<impl AsyncRead + AsyncWrite, Codec>,
+    message: IncomingMessage,
+) {
+    let message = serde_json::to_string(&message).unwrap();
+    framed.send(ws::Message::Text(message)).await.unwrap();
+}
+
+async fn send_request(
+    framed: &mut Framed<impl AsyncRead + AsyncWrite, Codec>,
+    request_id: RequestId,
+    message: IncomingMessage,
+) {
+    let envelope = IncomingEnvelope {
+        request_id: Some(request_id),
+        message,
+    };
+    let message = serde_json::to_string(&envelope).unwrap();
+    framed.send(ws::Message::Text(message)).await.unwrap();
+}
+
+async fn login(
+    framed: &mut Framed<impl AsyncRead + AsyncWrite, Codec>,
+    username: &str,
+) -> OutgoingClient {
+    let message = IncomingMessage::Login(IncomingLogin {
+        username: username.to_owned(),
+        password: "password".to_owned(),
+    });
+    send_message(framed, message).await;
+    let client = frame_message_type!(*framed, OutgoingMessage::Client);
+    frame_message_type!(*framed, OutgoingMessage::Snapshot);
+    client
+}
+
+/// Payload creating an issue like the given one
+fn copy_issue(issue: &Issue) -> IncomingIssue {
+    IncomingIssue {
+        title: issue.title.clone(),
+        description: issue.description.clone(),
+        alternatives: issue.alternatives.clone(),
+        max_voters: issue.max_voters,
+        show_distribution: issue.show_distribution,
+        allow_vote_change: issue.allow_vote_change,
+        secret: issue.secret,
+        voting_method: issue.voting_method,
+        min_choices: issue.min_choices,
+        max_choices: issue.max_choices,
+        rules: issue.rules.clone(),
+        max_weight: issue.max_weight,
+        meeting_id: issue.meeting_id.clone(),
+    }
+}
+
+async fn user_id(pool: &PgPool, username: &str) -> UserId {
+    let (id,): (Uuid,) = sqlx::query_as("SELECT id FROM users WHERE username = $1")
+        .bind(username)
+        .fetch_one(pool)
+        .await
+        .unwrap();
+    UserId(id)
+}
+
 #[actix_rt::test]
 async fn test_login_user() {
     setup_once();
@@ -81,7 +156,7 @@ async fn test_login_user() {
     let mut srv = test::start(move || {
         server::register_db_actor(pool.clone());
         server::register_system_actors();
-        App::new().configure(|app| server::configure(app))
+        App::new().configure(server::configure)
     });
     let mut framed = srv.ws_at("/ws/").await.unwrap();
     // Wait until issue has been sent
@@ -90,6 +165,7 @@ async fn test_login_user() {
     // Send user login
     let message = IncomingMessage::Login(IncomingLogin {
         username: "user".to_owned(),
+        password: "password".to_owned(),
     });
     let message = serde_json::to_string(&message).unwrap();
     framed.send(ws::Message::Text(message)).await.unwrap();
@@ -107,7 +183,7 @@ async fn test_reconnect() {
     let mut srv = test::start(move || {
         server::register_db_actor(pool.clone());
         server::register_system_actors();
-        App::new().configure(|app| server::configure(app))
+        App::new().configure(server::configure)
     });
     let mut framed = srv.ws_at("/ws/").await.unwrap();
     // Wait until issue has been sent
@@ -116,6 +192,7 @@ async fn test_reconnect() {
     // Send user login
     let message = IncomingMessage::Login(IncomingLogin {
         username: "user".to_owned(),
+        password: "password".to_owned(),
     });
     let message = serde_json::to_string(&message).unwrap();
     framed.send(ws::Message::Text(message)).await.unwrap();
@@ -157,7 +234,7 @@ async fn test_vote() {
     let mut srv = test::start(move || {
         server::register_db_actor(pool.clone());
         server::register_system_actors();
-        App::new().configure(|app| server::configure(app))
+        App::new().configure(server::configure)
     });
 
     let mut framed = srv.ws_at("/ws/").await.unwrap();
@@ -167,24 +244,21 @@ async fn test_vote() {
     let alternative_id = issue.alternatives[0].id.clone().unwrap();
 
     // Login
-    let message = IncomingMessage::Login(IncomingLogin {
-        username: "user".to_owned(),
-    });
-    let message = serde_json::to_string(&message).unwrap();
-    framed.send(ws::Message::Text(message)).await.unwrap();
-    frame_message_type!(framed, OutgoingMessage::Client);
+    login(&mut framed, "user").await;
 
     // Send vote
     let message = IncomingMessage::Vote(IncomingVote {
         user_id: None,
         issue_id: issue.id.clone().unwrap(),
-        alternative_id: alternative_id.clone(),
+        alternative_id: Some(alternative_id.clone()),
+        alternative_ids: Vec::new(),
+        on_behalf_of: None,
     });
     let message = serde_json::to_string(&message).unwrap();
     framed.send(ws::Message::Text(message)).await.unwrap();
 
     let vote = frame_message_type!(framed, OutgoingMessage::Vote);
-    assert_eq!(vote.alternative_id, alternative_id);
+    assert_eq!(vote.alternative_id, Some(alternative_id));
 
     // Close connection
     framed
@@ -209,7 +283,7 @@ async fn test_connect() {
     let mut srv = test::start(move || {
         server::register_db_actor(pool.clone());
         server::register_system_actors();
-        App::new().configure(|app| server::configure(app))
+        App::new().configure(server::configure)
     });
     let mut framed = srv.ws_at("/ws/").await.unwrap();
 
@@ -217,3 +291,2047 @@ async fn test_connect() {
 
     assert_ron_snapshot!(messages, { ".**.id" => "[uuid]" });
 }
+
+#[actix_rt::test]
+async fn test_issue_lifecycle() {
+    setup_once();
+    // Setup test server
+    let test_db = IntegrationTestDb::new().await;
+    let pool = test_db.pool();
+    let db_pool = pool.clone();
+    let mut srv = test::start(move || {
+        server::register_db_actor(pool.clone());
+        server::register_system_actors();
+        App::new().configure(server::configure)
+    });
+    let mut framed = srv.ws_at("/ws/").await.unwrap();
+    let issue = frame_message_type!(framed, OutgoingMessage::Issue);
+    assert_eq!(issue.state, Some(IssueState::InProgress));
+    let issue_id = issue.id.unwrap();
+
+    login(&mut framed, "admin").await;
+
+    // In progress -> finished
+    let message = IncomingMessage::FinishIssue(IncomingIssueStateChange {
+        issue_id: issue_id.clone(),
+    });
+    send_message(&mut framed, message).await;
+    let issue = frame_message_type!(framed, OutgoingMessage::Issue);
+    assert_eq!(issue.state, Some(IssueState::Finished));
+    frame_message_type!(framed, OutgoingMessage::Result);
+
+    // A finished issue can't be started again without a reset
+    let message = IncomingMessage::StartIssue(IncomingIssueStateChange {
+        issue_id: issue_id.clone(),
+    });
+    send_message(&mut framed, message).await;
+    let error = frame_message_type!(framed, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::InvalidTransition);
+
+    // Resetting a finished issue discards its result and must be confirmed
+    let message = IncomingMessage::ResetIssue(IncomingResetIssue {
+        issue_id: issue_id.clone(),
+        confirm: false,
+    });
+    send_message(&mut framed, message).await;
+    let error = frame_message_type!(framed, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::ConfirmationRequired);
+
+    // Finished -> not started -> in progress
+    let message = IncomingMessage::ResetIssue(IncomingResetIssue {
+        issue_id: issue_id.clone(),
+        confirm: true,
+    });
+    send_message(&mut framed, message).await;
+    let issue = frame_message_type!(framed, OutgoingMessage::Issue);
+    assert_eq!(issue.state, Some(IssueState::NotStarted));
+
+    let entries: Vec<(String, Uuid, String)> = sqlx::query_as(
+        "SELECT users.username::text, audit_log.issue_id, action FROM audit_log
+        JOIN users ON users.id = audit_log.actor_id",
+    )
+    .fetch_all(&db_pool)
+    .await
+    .unwrap();
+    assert_eq!(
+        entries,
+        vec![(
+            "admin".to_owned(),
+            issue_id.0,
+            "reset_finished_issue".to_owned()
+        )]
+    );
+
+    let message = IncomingMessage::StartIssue(IncomingIssueStateChange { issue_id });
+    send_message(&mut framed, message).await;
+    let issue = frame_message_type!(framed, OutgoingMessage::Issue);
+    assert_eq!(issue.state, Some(IssueState::InProgress));
+}
+
+#[actix_rt::test]
+async fn test_vote_on_finished_issue() {
+    setup_once();
+    // Setup test server
+    let test_db = IntegrationTestDb::new().await;
+    let pool = test_db.pool();
+    let mut srv = test::start(move || {
+        server::register_db_actor(pool.clone());
+        server::register_system_actors();
+        App::new().configure(server::configure)
+    });
+    let mut framed = srv.ws_at("/ws/").await.unwrap();
+    let issue = frame_message_type!(framed, OutgoingMessage::Issue);
+    let issue_id = issue.id.unwrap();
+    let alternative_id = issue.alternatives[0].id.clone().unwrap();
+
+    login(&mut framed, "admin").await;
+
+    let message = IncomingMessage::FinishIssue(IncomingIssueStateChange {
+        issue_id: issue_id.clone(),
+    });
+    send_message(&mut framed, message).await;
+    frame_message_type!(framed, OutgoingMessage::Issue);
+    frame_message_type!(framed, OutgoingMessage::Result);
+
+    // Vote is rejected and never broadcasted
+    let message = IncomingMessage::Vote(IncomingVote {
+        user_id: None,
+        issue_id,
+        alternative_id: Some(alternative_id),
+        alternative_ids: Vec::new(),
+        on_behalf_of: None,
+    });
+    send_message(&mut framed, message).await;
+    let error = frame_message_type!(framed, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::IssueNotInProgress);
+}
+
+#[actix_rt::test]
+async fn test_max_voters() {
+    setup_once();
+    // Setup test server
+    let test_db = IntegrationTestDb::new().await;
+    let pool = test_db.pool();
+    sqlx::query("UPDATE issues SET max_voters = 1")
+        .execute(&pool)
+        .await
+        .unwrap();
+    let mut srv = test::start(move || {
+        server::register_db_actor(pool.clone());
+        server::register_system_actors();
+        App::new().configure(server::configure)
+    });
+    let mut framed = srv.ws_at("/ws/").await.unwrap();
+    let issue = frame_message_type!(framed, OutgoingMessage::Issue);
+    let issue_id = issue.id.unwrap();
+    let alternative_id = issue.alternatives[0].id.clone().unwrap();
+
+    login(&mut framed, "user").await;
+
+    // Last available vote finishes the issue
+    let message = IncomingMessage::Vote(IncomingVote {
+        user_id: None,
+        issue_id: issue_id.clone(),
+        alternative_id: Some(alternative_id.clone()),
+        alternative_ids: Vec::new(),
+        on_behalf_of: None,
+    });
+    send_message(&mut framed, message).await;
+    frame_message_type!(framed, OutgoingMessage::Vote);
+    let issue = frame_message_type!(framed, OutgoingMessage::Issue);
+    assert_eq!(issue.state, Some(IssueState::Finished));
+    assert_eq!(issue.votes.unwrap().len(), 1);
+    let result = frame_message_type!(framed, OutgoingMessage::Result);
+    assert_eq!(result.winners, vec![alternative_id.clone()]);
+
+    // Other voters are rejected since the issue has been finished
+    let mut framed = srv.ws_at("/ws/").await.unwrap();
+    frame_message_type!(framed, OutgoingMessage::Issue);
+    frame_message_type!(framed, OutgoingMessage::Result);
+    login(&mut framed, "admin").await;
+    let message = IncomingMessage::Vote(IncomingVote {
+        user_id: None,
+        issue_id,
+        alternative_id: Some(alternative_id),
+        alternative_ids: Vec::new(),
+        on_behalf_of: None,
+    });
+    send_message(&mut framed, message).await;
+    let error = frame_message_type!(framed, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::IssueNotInProgress);
+}
+
+#[actix_rt::test]
+async fn test_active_issue() {
+    setup_once();
+    // Setup test server
+    let test_db = IntegrationTestDb::new().await;
+    let pool = test_db.pool();
+    let mut srv = test::start(move || {
+        server::register_db_actor(pool.clone());
+        server::register_system_actors();
+        App::new().configure(server::configure)
+    });
+    let mut framed = srv.ws_at("/ws/").await.unwrap();
+    let fixture_issue = frame_message_type!(framed, OutgoingMessage::Issue);
+
+    login(&mut framed, "admin").await;
+
+    let message = IncomingMessage::CreateIssue(IncomingCreateIssue {
+        issue: IncomingIssue {
+            title: "new issue".to_owned(),
+            description: "description".to_owned(),
+            alternatives: vec![Alternative {
+                id: None,
+                title: "alternative".to_owned(),
+            }],
+            max_voters: None,
+            show_distribution: true,
+            allow_vote_change: false,
+            secret: false,
+            voting_method: VotingMethod::Plurality,
+            min_choices: None,
+            max_choices: None,
+            rules: Default::default(),
+            max_weight: None,
+            meeting_id: None,
+        },
+    });
+    send_message(&mut framed, message).await;
+    let new_issue = frame_message_type!(framed, OutgoingMessage::IssueCreated).issue;
+    // Issues outside of any meeting don't depend on the number of users, who may still register
+    assert_eq!(new_issue.max_voters, Some(1000));
+
+    // Creating an issue doesn't replace the shown issue until it is activated
+    let mut other_framed = srv.ws_at("/ws/").await.unwrap();
+    let issue = frame_message_type!(other_framed, OutgoingMessage::Issue);
+    assert_eq!(issue.id, fixture_issue.id);
+
+    // Activating an issue is broadcasted to all clients
+    let message = IncomingMessage::ActivateIssue(IncomingActivateIssue {
+        issue_id: new_issue.id.clone().unwrap(),
+    });
+    send_message(&mut framed, message).await;
+    let issue = frame_message_type!(framed, OutgoingMessage::Issue);
+    assert_eq!(issue.id, new_issue.id);
+    let issue = frame_message_type!(other_framed, OutgoingMessage::Issue);
+    assert_eq!(issue.id, new_issue.id);
+
+    // State changes of other issues don't replace the shown issue
+    let message = IncomingMessage::FinishIssue(IncomingIssueStateChange {
+        issue_id: fixture_issue.id.clone().unwrap(),
+    });
+    send_message(&mut framed, message).await;
+    frame_message_type!(other_framed, OutgoingMessage::Result);
+    let message = IncomingMessage::StartIssue(IncomingIssueStateChange {
+        issue_id: new_issue.id.clone().unwrap(),
+    });
+    send_message(&mut framed, message).await;
+    let issue = frame_message_type!(other_framed, OutgoingMessage::Issue);
+    assert_eq!(issue.id, new_issue.id);
+    assert_eq!(issue.state, Some(IssueState::InProgress));
+
+    let mut framed = srv.ws_at("/ws/").await.unwrap();
+    let issue = frame_message_type!(framed, OutgoingMessage::Issue);
+    assert_eq!(issue.id, new_issue.id);
+}
+
+#[actix_rt::test]
+async fn test_create_issue() {
+    setup_once();
+    // Setup test server
+    let test_db = IntegrationTestDb::new().await;
+    let pool = test_db.pool();
+    let mut srv = test::start(move || {
+        server::register_db_actor(pool.clone());
+        server::register_system_actors();
+        App::new().configure(server::configure)
+    });
+    let mut framed = srv.ws_at("/ws/").await.unwrap();
+    frame_message_type!(framed, OutgoingMessage::Issue);
+    let mut other_framed = srv.ws_at("/ws/").await.unwrap();
+    frame_message_type!(other_framed, OutgoingMessage::Issue);
+
+    login(&mut framed, "admin").await;
+
+    let message = IncomingMessage::CreateIssue(IncomingCreateIssue {
+        issue: IncomingIssue {
+            title: "new issue".to_owned(),
+            description: "description".to_owned(),
+            alternatives: vec![
+                Alternative {
+                    id: None,
+                    title: "first".to_owned(),
+                },
+                Alternative {
+                    id: None,
+                    title: "second".to_owned(),
+                },
+            ],
+            max_voters: Some(5),
+            show_distribution: false,
+            allow_vote_change: false,
+            secret: false,
+            voting_method: VotingMethod::Plurality,
+            min_choices: None,
+            max_choices: None,
+            rules: Default::default(),
+            max_weight: None,
+            meeting_id: None,
+        },
+    });
+    send_request(&mut framed, RequestId::Number(1), message).await;
+
+    // Both the creator and other clients receive the persisted issue, the creator in reply to its request
+    let created = frame_message_type!(framed, OutgoingMessage::IssueCreated);
+    assert_eq!(created.request_id, Some(RequestId::Number(1)));
+    assert_eq!(created.issue.state, Some(IssueState::NotStarted));
+    assert!(created
+        .issue
+        .alternatives
+        .iter()
+        .all(|alt| alt.id.is_some()));
+    let messages = read_messages(&mut other_framed).await;
+    assert_ron_snapshot!(messages, { ".**.id" => "[uuid]" });
+}
+
+#[actix_rt::test]
+async fn test_permission_denied() {
+    setup_once();
+    // Setup test server
+    let test_db = IntegrationTestDb::new().await;
+    let pool = test_db.pool();
+    let mut srv = test::start(move || {
+        server::register_db_actor(pool.clone());
+        server::register_system_actors();
+        App::new().configure(server::configure)
+    });
+    let mut framed = srv.ws_at("/ws/").await.unwrap();
+    let issue = frame_message_type!(framed, OutgoingMessage::Issue);
+    let issue_id = issue.id.unwrap();
+    let alternative_id = issue.alternatives[0].id.clone().unwrap();
+
+    // Voters can't manage issues
+    login(&mut framed, "user").await;
+    let message = IncomingMessage::FinishIssue(IncomingIssueStateChange {
+        issue_id: issue_id.clone(),
+    });
+    send_message(&mut framed, message).await;
+    let messages = read_messages(&mut framed).await;
+    assert_ron_snapshot!("permission denied - voter", messages);
+
+    // Observers can't vote
+    let mut framed = srv.ws_at("/ws/").await.unwrap();
+    frame_message_type!(framed, OutgoingMessage::Issue);
+    login(&mut framed, "observer").await;
+    let message = IncomingMessage::Vote(IncomingVote {
+        user_id: None,
+        issue_id,
+        alternative_id: Some(alternative_id),
+        alternative_ids: Vec::new(),
+        on_behalf_of: None,
+    });
+    send_message(&mut framed, message).await;
+    let messages = read_messages(&mut framed).await;
+    assert_ron_snapshot!("permission denied - observer", messages);
+}
+
+#[actix_rt::test]
+async fn test_error_replies() {
+    setup_once();
+    // Setup test server
+    let test_db = IntegrationTestDb::new().await;
+    let pool = test_db.pool();
+    let mut srv = test::start(move || {
+        server::register_db_actor(pool.clone());
+        server::register_system_actors();
+        App::new().configure(server::configure)
+    });
+    let mut framed = srv.ws_at("/ws/").await.unwrap();
+    let issue = frame_message_type!(framed, OutgoingMessage::Issue);
+    let issue_id = issue.id.unwrap();
+    let alternative_id = issue.alternatives[0].id.clone().unwrap();
+
+    // Undecodable messages
+    let message = r#"{"type": "unknown", "request_id": "bad json"}"#.to_owned();
+    framed.send(ws::Message::Text(message)).await.unwrap();
+    let message = "not json".to_owned();
+    framed.send(ws::Message::Text(message)).await.unwrap();
+    let messages = read_messages(&mut framed).await;
+    assert_ron_snapshot!("error replies - bad request", messages);
+
+    // Voting without logging in
+    let vote = || {
+        IncomingMessage::Vote(IncomingVote {
+            user_id: None,
+            issue_id: issue_id.clone(),
+            alternative_id: Some(alternative_id.clone()),
+            alternative_ids: Vec::new(),
+            on_behalf_of: None,
+        })
+    };
+    send_request(&mut framed, RequestId::Number(1), vote()).await;
+    let messages = read_messages(&mut framed).await;
+    assert_ron_snapshot!("error replies - not logged in", messages);
+
+    // Voting twice
+    login(&mut framed, "user").await;
+    send_message(&mut framed, vote()).await;
+    frame_message_type!(framed, OutgoingMessage::Vote);
+    send_request(&mut framed, RequestId::Number(3), vote()).await;
+    let messages = read_messages(&mut framed).await;
+    assert_ron_snapshot!("error replies - already voted", messages);
+}
+
+#[actix_rt::test]
+async fn test_request_ids() {
+    setup_once();
+    // Setup test server
+    let test_db = IntegrationTestDb::new().await;
+    let pool = test_db.pool();
+    let mut srv = test::start(move || {
+        server::register_db_actor(pool.clone());
+        server::register_system_actors();
+        App::new().configure(server::configure)
+    });
+    let mut framed = srv.ws_at("/ws/").await.unwrap();
+    let issue = frame_message_type!(framed, OutgoingMessage::Issue);
+
+    let message = IncomingMessage::Login(IncomingLogin {
+        username: "admin".to_owned(),
+        password: "password".to_owned(),
+    });
+    send_request(&mut framed, RequestId::String("login".to_owned()), message).await;
+    let client = frame_message_type!(framed, OutgoingMessage::Client);
+    assert_eq!(
+        client.request_id,
+        Some(RequestId::String("login".to_owned()))
+    );
+    frame_message_type!(framed, OutgoingMessage::Snapshot);
+
+    // Requests without a direct reply are acknowledged while broadcasts stay uncorrelated
+    let message = IncomingMessage::Vote(IncomingVote {
+        user_id: None,
+        issue_id: issue.id.unwrap(),
+        alternative_id: Some(issue.alternatives[0].id.clone().unwrap()),
+        alternative_ids: Vec::new(),
+        on_behalf_of: None,
+    });
+    send_request(&mut framed, RequestId::Number(42), message).await;
+    let mut messages = read_messages(&mut framed).await;
+    messages.sort_by_key(|message| match message {
+        OutgoingMessage::Ack(_) => 0,
+        _ => 1,
+    });
+    assert_ron_snapshot!(messages, { ".**.id" => "[uuid]", ".**.alternative_id" => "[uuid]", ".**.user_id" => "[uuid]" });
+}
+
+#[actix_rt::test]
+async fn test_login_failures() {
+    setup_once();
+    // Setup test server
+    let test_db = IntegrationTestDb::new().await;
+    let pool = test_db.pool();
+    let mut srv = test::start(move || {
+        server::register_db_actor(pool.clone());
+        server::register_system_actors();
+        App::new().configure(server::configure)
+    });
+    let mut framed = srv.ws_at("/ws/").await.unwrap();
+    frame_message_type!(framed, OutgoingMessage::Issue);
+
+    let login = |password: &str| {
+        IncomingMessage::Login(IncomingLogin {
+            username: "user".to_owned(),
+            password: password.to_owned(),
+        })
+    };
+    for _ in 0..5 {
+        send_message(&mut framed, login("wrong")).await;
+        let error = frame_message_type!(framed, OutgoingMessage::Error);
+        assert_eq!(error.code, ErrorCode::InvalidCredentials);
+    }
+
+    // User is locked out even with the right password, without revealing the lockout
+    send_message(&mut framed, login("password")).await;
+    let messages = read_messages(&mut framed).await;
+    assert_ron_snapshot!(messages);
+
+    // Unknown users get the same error
+    let message = IncomingMessage::Login(IncomingLogin {
+        username: "unknown".to_owned(),
+        password: "password".to_owned(),
+    });
+    send_message(&mut framed, message).await;
+    let error = frame_message_type!(framed, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::InvalidCredentials);
+}
+
+#[actix_rt::test]
+async fn test_registration() {
+    setup_once();
+    // Setup test server
+    let test_db = IntegrationTestDb::new().await;
+    let pool = test_db.pool();
+    let mut srv = test::start(move || {
+        server::register_db_actor(pool.clone());
+        server::register_system_actors();
+        App::new().configure(server::configure)
+    });
+    let mut framed = srv.ws_at("/ws/").await.unwrap();
+    frame_message_type!(framed, OutgoingMessage::Issue);
+
+    let register = |username: &str| {
+        IncomingMessage::Registration(IncomingRegistration {
+            username: username.to_owned(),
+            password: "password".to_owned(),
+        })
+    };
+    send_message(&mut framed, register("new_user")).await;
+    let client = frame_message_type!(framed, OutgoingMessage::Client);
+    assert_ron_snapshot!(client, { ".id" => "[uuid]" });
+
+    // Usernames are unique regardless of case
+    send_message(&mut framed, register("New_User")).await;
+    let error = frame_message_type!(framed, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::UsernameTaken);
+
+    for username in &["ab", "has space", "ümlaut", &"x".repeat(41)] {
+        send_message(&mut framed, register(username)).await;
+        let error = frame_message_type!(framed, OutgoingMessage::Error);
+        assert_eq!(error.code, ErrorCode::InvalidUsername, "{}", username);
+    }
+
+    for password in &["", "short"] {
+        let message = IncomingMessage::Registration(IncomingRegistration {
+            username: "other_user".to_owned(),
+            password: (*password).to_owned(),
+        });
+        send_message(&mut framed, message).await;
+        let error = frame_message_type!(framed, OutgoingMessage::Error);
+        assert_eq!(error.code, ErrorCode::InvalidPassword, "{:?}", password);
+    }
+
+    // Login is case-insensitive as well
+    send_message(
+        &mut framed,
+        IncomingMessage::Login(IncomingLogin {
+            username: "NEW_USER".to_owned(),
+            password: "password".to_owned(),
+        }),
+    )
+    .await;
+    let client = frame_message_type!(framed, OutgoingMessage::Client);
+    assert_eq!(client.username.as_deref(), Some("new_user"));
+}
+
+#[actix_rt::test]
+async fn test_set_password() {
+    setup_once();
+    // Setup test server
+    let test_db = IntegrationTestDb::new().await;
+    let pool = test_db.pool();
+    sqlx::query("INSERT INTO users (username) VALUES ('imported')")
+        .execute(&pool)
+        .await
+        .unwrap();
+    let imported_id = user_id(&pool, "imported").await;
+    let mut srv = test::start(move || {
+        server::register_db_actor(pool.clone());
+        server::register_system_actors();
+        App::new().configure(server::configure)
+    });
+    let mut framed = srv.ws_at("/ws/").await.unwrap();
+    frame_message_type!(framed, OutgoingMessage::Issue);
+    let login_imported = |password: &str| {
+        IncomingMessage::Login(IncomingLogin {
+            username: "imported".to_owned(),
+            password: password.to_owned(),
+        })
+    };
+    let set_password = |user_id: &UserId, password: &str| {
+        IncomingMessage::SetPassword(IncomingSetPassword {
+            user_id: user_id.clone(),
+            password: password.to_owned(),
+        })
+    };
+
+    // Users added without a password can't log in until it is set
+    send_message(&mut framed, login_imported("")).await;
+    let error = frame_message_type!(framed, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::InvalidCredentials);
+
+    // Only admins set passwords
+    login(&mut framed, "user").await;
+    send_message(&mut framed, set_password(&imported_id, "new password")).await;
+    let error = frame_message_type!(framed, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::PermissionDenied);
+    let mut admin = srv.ws_at("/ws/").await.unwrap();
+    frame_message_type!(admin, OutgoingMessage::Issue);
+    login(&mut admin, "admin").await;
+    send_message(&mut admin, set_password(&imported_id, "short")).await;
+    let error = frame_message_type!(admin, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::InvalidPassword);
+    send_message(&mut admin, set_password(&UserId::new(), "new password")).await;
+    let error = frame_message_type!(admin, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::UserNotFound);
+    let message = set_password(&imported_id, "new password");
+    send_request(&mut admin, RequestId::Number(1), message).await;
+    frame_message_type!(admin, OutgoingMessage::Ack);
+
+    let mut imported = srv.ws_at("/ws/").await.unwrap();
+    frame_message_type!(imported, OutgoingMessage::Issue);
+    send_message(&mut imported, login_imported("new password")).await;
+    let client = frame_message_type!(imported, OutgoingMessage::Client);
+    assert_eq!(client.username.as_deref(), Some("imported"));
+}
+
+#[actix_rt::test]
+async fn test_session_lifecycle() {
+    setup_once();
+    // Setup test server
+    let test_db = IntegrationTestDb::new().await;
+    let pool = test_db.pool();
+    let user_id = user_id(&pool, "user").await;
+    let db_pool = pool.clone();
+    let mut srv = test::start(move || {
+        server::register_db_actor(pool.clone());
+        server::register_system_actors();
+        App::new().configure(server::configure)
+    });
+    let reconnect = |session_id| IncomingMessage::Reconnect(IncomingReconnect { session_id });
+
+    // Logged out sessions can't be reused
+    let mut framed = srv.ws_at("/ws/").await.unwrap();
+    frame_message_type!(framed, OutgoingMessage::Issue);
+    let client = login(&mut framed, "user").await;
+    send_request(&mut framed, RequestId::Number(1), IncomingMessage::Logout).await;
+    let ack = frame_message_type!(framed, OutgoingMessage::Ack);
+    assert_eq!(ack.request_id, Some(RequestId::Number(1)));
+    send_message(&mut framed, IncomingMessage::Logout).await;
+    let error = frame_message_type!(framed, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::NotLoggedIn);
+    send_message(&mut framed, reconnect(client.id)).await;
+    let error = frame_message_type!(framed, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::InvalidSession);
+
+    // Connected clients are logged out once their session expires
+    let client = login(&mut framed, "user").await;
+    sqlx::query("UPDATE sessions SET expires_at = now() - interval '1 minute' WHERE id = $1")
+        .bind(client.id.0)
+        .execute(&db_pool)
+        .await
+        .unwrap();
+    send_message(&mut framed, IncomingMessage::Logout).await;
+    let expired = read_message(&mut framed).await;
+    assert!(matches!(expired, Some(OutgoingMessage::SessionExpired)));
+    let error = frame_message_type!(framed, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::NotLoggedIn);
+
+    // Expired sessions are rejected
+    send_message(&mut framed, reconnect(client.id)).await;
+    let error = frame_message_type!(framed, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::InvalidSession);
+
+    // Admins can revoke all sessions of a user
+    let client = login(&mut framed, "user").await;
+    let mut admin = srv.ws_at("/ws/").await.unwrap();
+    frame_message_type!(admin, OutgoingMessage::Issue);
+    login(&mut admin, "admin").await;
+    let revoke = || {
+        IncomingMessage::RevokeUserSessions(IncomingRevokeUserSessions {
+            user_id: user_id.clone(),
+        })
+    };
+    send_message(&mut admin, revoke()).await;
+    let revoked = read_message(&mut framed).await;
+    assert!(matches!(revoked, Some(OutgoingMessage::SessionRevoked)));
+    assert!(read_messages(&mut admin).await.is_empty());
+    send_message(&mut framed, reconnect(client.id)).await;
+    let error = frame_message_type!(framed, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::InvalidSession);
+
+    // Other users are not allowed to
+    login(&mut framed, "user").await;
+    send_message(&mut framed, revoke()).await;
+    let error = frame_message_type!(framed, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::PermissionDenied);
+}
+
+#[actix_rt::test]
+async fn test_snapshot_restores_vote() {
+    setup_once();
+    // Setup test server
+    let test_db = IntegrationTestDb::new().await;
+    let pool = test_db.pool();
+    let mut srv = test::start(move || {
+        server::register_db_actor(pool.clone());
+        server::register_system_actors();
+        App::new().configure(server::configure)
+    });
+    let mut framed = srv.ws_at("/ws/").await.unwrap();
+    let issue = frame_message_type!(framed, OutgoingMessage::Issue);
+    let alternative_id = issue.alternatives[1].id.clone().unwrap();
+    let client = login(&mut framed, "user").await;
+    let message = IncomingMessage::Vote(IncomingVote {
+        user_id: None,
+        issue_id: issue.id.clone().unwrap(),
+        alternative_id: Some(alternative_id.clone()),
+        alternative_ids: Vec::new(),
+        on_behalf_of: None,
+    });
+    send_message(&mut framed, message).await;
+    frame_message_type!(framed, OutgoingMessage::Vote);
+
+    // Reloading the page restores the issue, the vote and the role
+    let mut framed = srv.ws_at("/ws/").await.unwrap();
+    frame_message_type!(framed, OutgoingMessage::Issue);
+    let message = IncomingMessage::Reconnect(IncomingReconnect {
+        session_id: client.id,
+    });
+    send_message(&mut framed, message).await;
+    frame_message_type!(framed, OutgoingMessage::Client);
+    let snapshot = frame_message_type!(framed, OutgoingMessage::Snapshot);
+    assert_eq!(snapshot.issue.unwrap().id, issue.id);
+    assert_eq!(snapshot.vote.unwrap().alternative_id, Some(alternative_id));
+    assert_eq!(snapshot.role, UserRole::Voter);
+}
+
+#[actix_rt::test]
+async fn test_change_vote() {
+    setup_once();
+    // Setup test server
+    let test_db = IntegrationTestDb::new().await;
+    let pool = test_db.pool();
+    sqlx::query("UPDATE issues SET allow_vote_change = true")
+        .execute(&pool)
+        .await
+        .unwrap();
+    let db_pool = pool.clone();
+    let mut srv = test::start(move || {
+        server::register_db_actor(pool.clone());
+        server::register_system_actors();
+        App::new().configure(server::configure)
+    });
+    let mut framed = srv.ws_at("/ws/").await.unwrap();
+    let issue = frame_message_type!(framed, OutgoingMessage::Issue);
+    assert!(issue.allow_vote_change);
+    let issue_id = issue.id.unwrap();
+    let mut other_framed = srv.ws_at("/ws/").await.unwrap();
+    frame_message_type!(other_framed, OutgoingMessage::Issue);
+
+    login(&mut framed, "user").await;
+    let vote = |alternative: &Alternative| {
+        IncomingMessage::Vote(IncomingVote {
+            user_id: None,
+            issue_id: issue_id.clone(),
+            alternative_id: Some(alternative.id.clone().unwrap()),
+            alternative_ids: Vec::new(),
+            on_behalf_of: None,
+        })
+    };
+    send_message(&mut framed, vote(&issue.alternatives[0])).await;
+    let first = frame_message_type!(framed, OutgoingMessage::Vote);
+    frame_message_type!(other_framed, OutgoingMessage::Vote);
+
+    // Voting again replaces the vote for everyone
+    send_message(&mut framed, vote(&issue.alternatives[1])).await;
+    let changed = frame_message_type!(framed, OutgoingMessage::VoteChanged);
+    let other_changed = frame_message_type!(other_framed, OutgoingMessage::VoteChanged);
+    assert_eq!(changed.alternative_id, issue.alternatives[1].id);
+    assert_eq!(other_changed.alternative_id, changed.alternative_id);
+    assert_eq!(changed.user_id, first.user_id);
+
+    let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM votes")
+        .fetch_one(&db_pool)
+        .await
+        .unwrap();
+    assert_eq!(count, 1);
+}
+
+#[actix_rt::test]
+async fn test_abstain_and_retract() {
+    setup_once();
+    // Setup test server
+    let test_db = IntegrationTestDb::new().await;
+    let pool = test_db.pool();
+    let db_pool = pool.clone();
+    let mut srv = test::start(move || {
+        server::register_db_actor(pool.clone());
+        server::register_system_actors();
+        App::new().configure(server::configure)
+    });
+    let mut framed = srv.ws_at("/ws/").await.unwrap();
+    let issue = frame_message_type!(framed, OutgoingMessage::Issue);
+    let issue_id = issue.id.unwrap();
+    let mut other_framed = srv.ws_at("/ws/").await.unwrap();
+    frame_message_type!(other_framed, OutgoingMessage::Issue);
+    login(&mut framed, "user").await;
+    let abstain = || {
+        IncomingMessage::Abstain(IncomingAbstain {
+            issue_id: issue_id.clone(),
+            on_behalf_of: None,
+        })
+    };
+    let retract = || {
+        IncomingMessage::RetractVote(IncomingRetractVote {
+            issue_id: issue_id.clone(),
+            on_behalf_of: None,
+        })
+    };
+
+    // Abstaining is stored as a vote without an alternative
+    send_message(&mut framed, abstain()).await;
+    let vote = frame_message_type!(framed, OutgoingMessage::Vote);
+    assert_eq!(vote.alternative_id, None);
+    let other_vote = frame_message_type!(other_framed, OutgoingMessage::Vote);
+    assert_eq!(other_vote.alternative_id, None);
+
+    // Retracting is a vote change
+    send_message(&mut framed, retract()).await;
+    let error = frame_message_type!(framed, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::VoteChangeNotAllowed);
+    sqlx::query("UPDATE issues SET allow_vote_change = true")
+        .execute(&db_pool)
+        .await
+        .unwrap();
+
+    send_message(&mut framed, retract()).await;
+    let retracted = frame_message_type!(framed, OutgoingMessage::VoteRetracted);
+    assert_eq!(retracted.user_id, vote.user_id);
+    frame_message_type!(other_framed, OutgoingMessage::VoteRetracted);
+    send_message(&mut framed, retract()).await;
+    let error = frame_message_type!(framed, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::NotVoted);
+
+    let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM votes")
+        .fetch_one(&db_pool)
+        .await
+        .unwrap();
+    assert_eq!(count, 0);
+}
+
+#[actix_rt::test]
+async fn test_secret_ballot() {
+    setup_once();
+    // Setup test server
+    let test_db = IntegrationTestDb::new().await;
+    let pool = test_db.pool();
+    sqlx::query(
+        "UPDATE issues SET secret = true, allow_vote_change = true, voting_method = 'approval'",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    sqlx::query("UPDATE users SET vote_weight = 3 WHERE username = 'admin'")
+        .execute(&pool)
+        .await
+        .unwrap();
+    let db_pool = pool.clone();
+    let mut srv = test::start(move || {
+        server::register_db_actor(pool.clone());
+        server::register_system_actors();
+        App::new().configure(server::configure)
+    });
+    let mut framed = srv.ws_at("/ws/").await.unwrap();
+    let issue = frame_message_type!(framed, OutgoingMessage::Issue);
+    assert!(issue.secret);
+    let issue_id = issue.id.unwrap();
+    // Voters choose alternatives in the reverse of their stored order
+    let mut alternative_ids: Vec<_> = issue
+        .alternatives
+        .iter()
+        .map(|alternative| alternative.id.clone().unwrap())
+        .collect();
+    alternative_ids.sort_by_key(|id| id.0);
+    let (user_choice, admin_choice) = (alternative_ids[1].clone(), alternative_ids[0].clone());
+    let vote = |alternative_id: &AlternativeId| {
+        IncomingMessage::Vote(IncomingVote {
+            user_id: None,
+            issue_id: issue_id.clone(),
+            alternative_id: None,
+            alternative_ids: vec![alternative_id.clone()],
+            on_behalf_of: None,
+        })
+    };
+
+    // Broadcasts don't reveal the voter or their weight, and the ballot has no id before it is published
+    login(&mut framed, "user").await;
+    send_message(&mut framed, vote(&user_choice)).await;
+    let user_vote = frame_message_type!(framed, OutgoingMessage::Vote);
+    assert_eq!(user_vote.id, None);
+    assert_eq!(user_vote.user_id, None);
+    assert_eq!(user_vote.weight, None);
+    let mut admin = srv.ws_at("/ws/").await.unwrap();
+    frame_message_type!(admin, OutgoingMessage::Issue);
+    login(&mut admin, "admin").await;
+    send_message(&mut admin, vote(&admin_choice)).await;
+    let admin_vote = frame_message_type!(admin, OutgoingMessage::Vote);
+    assert_eq!(admin_vote.id, None);
+    assert_eq!(admin_vote.user_id, None);
+    assert_eq!(admin_vote.weight, None);
+    frame_message_type!(framed, OutgoingMessage::Vote);
+
+    // The receipt still prevents voting twice, even if vote changes are allowed
+    send_message(&mut framed, vote(&user_choice)).await;
+    let error = frame_message_type!(framed, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::AlreadyVoted);
+
+    // Ballots are published once the issue finishes
+    let message = IncomingMessage::FinishIssue(IncomingIssueStateChange { issue_id });
+    send_message(&mut admin, message).await;
+    frame_message_type!(admin, OutgoingMessage::Issue);
+    let result = frame_message_type!(admin, OutgoingMessage::Result);
+    let (queued,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM secret_ballots")
+        .fetch_one(&db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued, 0);
+
+    // Receipts know who voted, but not how
+    let (user_id, admin_id) = (
+        user_id(&db_pool, "user").await.0,
+        user_id(&db_pool, "admin").await.0,
+    );
+    let receipts: Vec<(Uuid, String)> =
+        sqlx::query_as("SELECT user_id, xmin::text FROM ballot_receipts ORDER BY ctid")
+            .fetch_all(&db_pool)
+            .await
+            .unwrap();
+    assert_eq!(receipts.len(), 2);
+    let stored: Vec<(Uuid, String)> =
+        sqlx::query_as("SELECT alternative_id, xmin::text FROM votes ORDER BY ctid")
+            .fetch_all(&db_pool)
+            .await
+            .unwrap();
+    let stored_choices: Vec<(String,)> = sqlx::query_as("SELECT xmin::text FROM vote_choices")
+        .fetch_all(&db_pool)
+        .await
+        .unwrap();
+    assert_eq!(stored_choices.len(), 2);
+    let stored_weights: Vec<(String,)> =
+        sqlx::query_as("SELECT xmin::text FROM secret_vote_weights")
+            .fetch_all(&db_pool)
+            .await
+            .unwrap();
+    assert_eq!(stored_weights.len(), 2);
+    // No ballot, choice or weight was written in the transaction of a receipt
+    assert!(receipts.iter().all(|(_, receipt_xmin)| stored
+        .iter()
+        .map(|(_, xmin)| xmin)
+        .chain(stored_choices.iter().map(|(xmin,)| xmin))
+        .chain(stored_weights.iter().map(|(xmin,)| xmin))
+        .all(|xmin| xmin != receipt_xmin)));
+    // Nor do they have timestamps to match
+    let (timestamps,): (i64,) = sqlx::query_as(
+        "SELECT COUNT(*) FROM information_schema.columns
+        WHERE table_name IN ('ballot_receipts', 'votes', 'vote_choices', 'secret_vote_weights', 'secret_ballots')
+        AND data_type LIKE 'timestamp%'",
+    )
+    .fetch_one(&db_pool)
+    .await
+    .unwrap();
+    assert_eq!(timestamps, 0);
+    // Pairing receipts and ballots in row order doesn't reveal the choices
+    let paired: Vec<(Uuid, Uuid)> = receipts
+        .iter()
+        .zip(&stored)
+        .map(|((voter, _), (alternative, _))| (*voter, *alternative))
+        .collect();
+    assert!(!paired.contains(&(user_id, user_choice.0)));
+    assert!(!paired.contains(&(admin_id, admin_choice.0)));
+    let ballots: Vec<(Option<Uuid>, Option<Uuid>, Option<i32>)> =
+        sqlx::query_as("SELECT user_id, cast_by, weight FROM votes")
+            .fetch_all(&db_pool)
+            .await
+            .unwrap();
+    assert_eq!(ballots.len(), 2);
+    assert!(ballots.iter().all(|ballot| ballot == &(None, None, None)));
+    // Weight is only kept summed up per choice
+    let weights: Vec<(Vec<Uuid>, i32, i64)> =
+        sqlx::query_as("SELECT choices, ballots, weight FROM secret_vote_weights ORDER BY weight")
+            .fetch_all(&db_pool)
+            .await
+            .unwrap();
+    assert_eq!(
+        weights,
+        vec![(vec![user_choice.0], 1, 1), (vec![admin_choice.0], 1, 3)]
+    );
+    // No other column of the ballots refers to the voter
+    let columns: Vec<(String,)> = sqlx::query_as(
+        "SELECT column_name::text FROM information_schema.columns
+        WHERE table_name = 'votes' ORDER BY column_name",
+    )
+    .fetch_all(&db_pool)
+    .await
+    .unwrap();
+    let columns: Vec<_> = columns.into_iter().map(|(name,)| name).collect();
+    assert_eq!(
+        columns,
+        [
+            "alternative_id",
+            "cast_by",
+            "id",
+            "issue_id",
+            "user_id",
+            "weight"
+        ]
+    );
+
+    // The result still counts the weight of the ballots
+    let weight_of = |alternative_id: &AlternativeId| {
+        result
+            .alternatives
+            .iter()
+            .find(|alternative| &alternative.alternative_id == alternative_id)
+            .map(|alternative| (alternative.votes, alternative.weight))
+    };
+    assert_eq!(weight_of(&user_choice), Some((1, 1)));
+    assert_eq!(weight_of(&admin_choice), Some((1, 3)));
+    assert_eq!(result.winners, vec![admin_choice]);
+    assert_eq!(result.total_weight, 4);
+}
+
+#[actix_rt::test]
+async fn test_hidden_distribution() {
+    setup_once();
+    // Setup test server
+    let test_db = IntegrationTestDb::new().await;
+    let pool = test_db.pool();
+    sqlx::query("UPDATE issues SET show_distribution = false")
+        .execute(&pool)
+        .await
+        .unwrap();
+    let mut srv = test::start(move || {
+        server::register_db_actor(pool.clone());
+        server::register_system_actors();
+        App::new().configure(server::configure)
+    });
+    let mut framed = srv.ws_at("/ws/").await.unwrap();
+    let issue = frame_message_type!(framed, OutgoingMessage::Issue);
+    assert!(issue.votes.is_none());
+    assert_eq!(issue.vote_count, Some(0));
+    let issue_id = issue.id.unwrap();
+    let mut admin = srv.ws_at("/ws/").await.unwrap();
+    frame_message_type!(admin, OutgoingMessage::Issue);
+    login(&mut admin, "admin").await;
+    let mut voter = srv.ws_at("/ws/").await.unwrap();
+    frame_message_type!(voter, OutgoingMessage::Issue);
+    login(&mut voter, "user").await;
+
+    // Only the voter and admins see the vote, everyone else only the count
+    let message = IncomingMessage::Vote(IncomingVote {
+        user_id: None,
+        issue_id: issue_id.clone(),
+        alternative_id: Some(issue.alternatives[0].id.clone().unwrap()),
+        alternative_ids: Vec::new(),
+        on_behalf_of: None,
+    });
+    send_message(&mut voter, message).await;
+    frame_message_type!(voter, OutgoingMessage::Vote);
+    frame_message_type!(admin, OutgoingMessage::Vote);
+    let count = read_messages(&mut framed).await;
+    assert_ron_snapshot!("hidden distribution - count", count, { ".**.issue_id" => "[uuid]" });
+
+    // The distribution is revealed once the issue is finished
+    let message = IncomingMessage::FinishIssue(IncomingIssueStateChange { issue_id });
+    send_message(&mut admin, message).await;
+    let finished = frame_message_type!(framed, OutgoingMessage::Issue);
+    assert_eq!(finished.votes.map(|votes| votes.len()), Some(1));
+}
+
+#[actix_rt::test]
+async fn test_results() {
+    setup_once();
+    // Setup test server
+    let test_db = IntegrationTestDb::new().await;
+    let pool = test_db.pool();
+    let mut srv = test::start(move || {
+        server::register_db_actor(pool.clone());
+        server::register_system_actors();
+        App::new().configure(server::configure)
+    });
+    let mut framed = srv.ws_at("/ws/").await.unwrap();
+    let issue = frame_message_type!(framed, OutgoingMessage::Issue);
+    let issue_id = issue.id.unwrap();
+    let vote = |alternative: &Alternative| {
+        IncomingMessage::Vote(IncomingVote {
+            user_id: None,
+            issue_id: issue_id.clone(),
+            alternative_id: Some(alternative.id.clone().unwrap()),
+            alternative_ids: Vec::new(),
+            on_behalf_of: None,
+        })
+    };
+
+    // One vote each for the first two alternatives is a tie
+    login(&mut framed, "user").await;
+    send_message(&mut framed, vote(&issue.alternatives[0])).await;
+    frame_message_type!(framed, OutgoingMessage::Vote);
+    let mut admin = srv.ws_at("/ws/").await.unwrap();
+    frame_message_type!(admin, OutgoingMessage::Issue);
+    login(&mut admin, "admin").await;
+    send_message(&mut admin, vote(&issue.alternatives[1])).await;
+    frame_message_type!(admin, OutgoingMessage::Vote);
+    let message = IncomingMessage::FinishIssue(IncomingIssueStateChange { issue_id });
+    send_message(&mut admin, message).await;
+    frame_message_type!(admin, OutgoingMessage::Issue);
+    let result = frame_message_type!(admin, OutgoingMessage::Result);
+    assert_ron_snapshot!("results", result, {
+        ".issue_id" => "[uuid]",
+        ".**.alternative_id" => "[uuid]",
+        ".winners[]" => "[uuid]",
+    });
+
+    // Late joiners get the stored result
+    let mut late = srv.ws_at("/ws/").await.unwrap();
+    frame_message_type!(late, OutgoingMessage::Issue);
+    let late_result = frame_message_type!(late, OutgoingMessage::Result);
+    assert_eq!(late_result, result);
+}
+
+#[actix_rt::test]
+async fn test_decision_rules() {
+    setup_once();
+    // Setup test server
+    let test_db = IntegrationTestDb::new().await;
+    let pool = test_db.pool();
+    // Two of the ten voters have to take part and the winner needs two thirds
+    sqlx::query(
+        "UPDATE issues SET quorum_percent = 20, majority = 'qualified',
+            qualified_numerator = 2, qualified_denominator = 3",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    let mut srv = test::start(move || {
+        server::register_db_actor(pool.clone());
+        server::register_system_actors();
+        App::new().configure(server::configure)
+    });
+    let mut admin = srv.ws_at("/ws/").await.unwrap();
+    let issue = frame_message_type!(admin, OutgoingMessage::Issue);
+    let issue_id = issue.id.clone().unwrap();
+    assert_eq!(
+        issue.rules.majority,
+        Majority::Qualified {
+            numerator: 2,
+            denominator: 3
+        }
+    );
+    login(&mut admin, "admin").await;
+
+    // Rules that can't be met are rejected
+    let mut new_issue = copy_issue(&issue);
+    new_issue.rules.majority = Majority::Qualified {
+        numerator: 3,
+        denominator: 2,
+    };
+    let message = IncomingMessage::CreateIssue(IncomingCreateIssue { issue: new_issue });
+    send_message(&mut admin, message).await;
+    let error = frame_message_type!(admin, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::InvalidRules);
+
+    let vote = || {
+        IncomingMessage::Vote(IncomingVote {
+            user_id: None,
+            issue_id: issue_id.clone(),
+            alternative_id: Some(issue.alternatives[0].id.clone().unwrap()),
+            alternative_ids: Vec::new(),
+            on_behalf_of: None,
+        })
+    };
+    let finish = || {
+        IncomingMessage::FinishIssue(IncomingIssueStateChange {
+            issue_id: issue_id.clone(),
+        })
+    };
+
+    // A single vote misses the quorum
+    send_message(&mut admin, vote()).await;
+    frame_message_type!(admin, OutgoingMessage::Vote);
+    send_message(&mut admin, finish()).await;
+    frame_message_type!(admin, OutgoingMessage::Issue);
+    let result = frame_message_type!(admin, OutgoingMessage::Result);
+    assert_eq!(result.outcome, Outcome::NoQuorum);
+
+    let message = IncomingMessage::ResetIssue(IncomingResetIssue {
+        issue_id: issue_id.clone(),
+        confirm: true,
+    });
+    send_message(&mut admin, message).await;
+    frame_message_type!(admin, OutgoingMessage::Issue);
+    let message = IncomingMessage::StartIssue(IncomingIssueStateChange {
+        issue_id: issue_id.clone(),
+    });
+    send_message(&mut admin, message).await;
+    frame_message_type!(admin, OutgoingMessage::Issue);
+
+    // Abstentions count towards the quorum but not the majority
+    send_message(&mut admin, vote()).await;
+    frame_message_type!(admin, OutgoingMessage::Vote);
+    let mut user = srv.ws_at("/ws/").await.unwrap();
+    frame_message_type!(user, OutgoingMessage::Issue);
+    login(&mut user, "user").await;
+    let message = IncomingMessage::Abstain(IncomingAbstain {
+        issue_id: issue_id.clone(),
+        on_behalf_of: None,
+    });
+    send_message(&mut user, message).await;
+    frame_message_type!(user, OutgoingMessage::Vote);
+    frame_message_type!(admin, OutgoingMessage::Vote);
+    send_message(&mut admin, finish()).await;
+    frame_message_type!(admin, OutgoingMessage::Issue);
+    let result = frame_message_type!(admin, OutgoingMessage::Result);
+    assert_eq!(result.abstentions, 1);
+    assert_eq!(result.outcome, Outcome::Passed);
+}
+
+#[actix_rt::test]
+async fn test_ranked_vote() {
+    setup_once();
+    // Setup test server
+    let test_db = IntegrationTestDb::new().await;
+    let pool = test_db.pool();
+    sqlx::query("UPDATE issues SET voting_method = 'ranked'")
+        .execute(&pool)
+        .await
+        .unwrap();
+    let mut srv = test::start(move || {
+        server::register_db_actor(pool.clone());
+        server::register_system_actors();
+        App::new().configure(server::configure)
+    });
+    let mut admin = srv.ws_at("/ws/").await.unwrap();
+    let issue = frame_message_type!(admin, OutgoingMessage::Issue);
+    assert_eq!(issue.voting_method, VotingMethod::Ranked);
+    let issue_id = issue.id.clone().unwrap();
+    let alternative = |i: usize| issue.alternatives[i].id.clone().unwrap();
+    let ranked = |ranking: Vec<AlternativeId>| {
+        IncomingMessage::RankedVote(IncomingRankedVote {
+            issue_id: issue_id.clone(),
+            ranking,
+            on_behalf_of: None,
+        })
+    };
+    login(&mut admin, "admin").await;
+
+    // Ballots have to fit the voting method
+    let message = IncomingMessage::Vote(IncomingVote {
+        user_id: None,
+        issue_id: issue_id.clone(),
+        alternative_id: Some(alternative(0)),
+        alternative_ids: Vec::new(),
+        on_behalf_of: None,
+    });
+    send_message(&mut admin, message).await;
+    let error = frame_message_type!(admin, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::InvalidBallot);
+    send_message(&mut admin, ranked(vec![alternative(0), alternative(0)])).await;
+    let error = frame_message_type!(admin, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::InvalidBallot);
+
+    // A leads the first preferences without a majority, B and C are eliminated together
+    // and B's ballot transfers to A while C's is exhausted
+    let mut voters = Vec::new();
+    for (username, ranking) in vec![
+        ("user", vec![alternative(1), alternative(0)]),
+        ("voter_c", vec![alternative(2), alternative(1)]),
+        ("voter_a", vec![alternative(0), alternative(2)]),
+    ] {
+        let mut voter = srv.ws_at("/ws/").await.unwrap();
+        frame_message_type!(voter, OutgoingMessage::Issue);
+        if username == "user" {
+            login(&mut voter, username).await;
+        } else {
+            let message = IncomingMessage::Registration(IncomingRegistration {
+                username: username.to_owned(),
+                password: "password".to_owned(),
+            });
+            send_message(&mut voter, message).await;
+            frame_message_type!(voter, OutgoingMessage::Client);
+        }
+        send_message(&mut voter, ranked(ranking)).await;
+        let vote = frame_message_type!(voter, OutgoingMessage::Vote);
+        voters.push(voter);
+        // Only the first choice is shown on the vote
+        assert!(vote.alternative_id.is_some());
+    }
+    send_message(&mut admin, ranked(vec![alternative(0)])).await;
+    read_messages(&mut admin).await;
+
+    let message = IncomingMessage::FinishIssue(IncomingIssueStateChange { issue_id });
+    send_message(&mut admin, message).await;
+    frame_message_type!(admin, OutgoingMessage::Issue);
+    let result = frame_message_type!(admin, OutgoingMessage::Result);
+    assert_eq!(result.rounds.len(), 2);
+    assert_eq!(
+        result.rounds[0].eliminated,
+        vec![alternative(1), alternative(2)]
+    );
+    assert_eq!(result.rounds[1].exhausted, 1);
+    assert_eq!(result.alternatives[0].votes, 3);
+    assert_eq!(result.winners, vec![alternative(0)]);
+    assert_eq!(result.outcome, Outcome::Passed);
+}
+
+#[actix_rt::test]
+async fn test_approval_vote() {
+    setup_once();
+    // Setup test server
+    let test_db = IntegrationTestDb::new().await;
+    let pool = test_db.pool();
+    sqlx::query("UPDATE issues SET voting_method = 'approval', max_choices = 2")
+        .execute(&pool)
+        .await
+        .unwrap();
+    let mut srv = test::start(move || {
+        server::register_db_actor(pool.clone());
+        server::register_system_actors();
+        App::new().configure(server::configure)
+    });
+    let mut admin = srv.ws_at("/ws/").await.unwrap();
+    let issue = frame_message_type!(admin, OutgoingMessage::Issue);
+    assert_eq!(issue.voting_method, VotingMethod::Approval);
+    assert_eq!((issue.min_choices, issue.max_choices), (Some(1), Some(2)));
+    let issue_id = issue.id.clone().unwrap();
+    let alternative = |i: usize| issue.alternatives[i].id.clone().unwrap();
+    let approve = |alternative_ids: Vec<AlternativeId>| {
+        IncomingMessage::Vote(IncomingVote {
+            user_id: None,
+            issue_id: issue_id.clone(),
+            alternative_id: None,
+            alternative_ids,
+            on_behalf_of: None,
+        })
+    };
+    login(&mut admin, "admin").await;
+
+    // Selections have to be within the limits and belong to the issue
+    let invalid = vec![
+        vec![alternative(0), alternative(1), alternative(2)],
+        vec![alternative(0), alternative(0)],
+        vec![AlternativeId(Uuid::new_v4())],
+    ];
+    for alternative_ids in invalid {
+        send_message(&mut admin, approve(alternative_ids)).await;
+        let error = frame_message_type!(admin, OutgoingMessage::Error);
+        assert_eq!(error.code, ErrorCode::InvalidBallot);
+    }
+    let message = IncomingMessage::Vote(IncomingVote {
+        user_id: None,
+        issue_id: issue_id.clone(),
+        alternative_id: Some(alternative(0)),
+        alternative_ids: Vec::new(),
+        on_behalf_of: None,
+    });
+    send_message(&mut admin, message).await;
+    let error = frame_message_type!(admin, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::InvalidBallot);
+
+    send_message(&mut admin, approve(vec![alternative(0), alternative(1)])).await;
+    frame_message_type!(admin, OutgoingMessage::Vote);
+    let mut user = srv.ws_at("/ws/").await.unwrap();
+    frame_message_type!(user, OutgoingMessage::Issue);
+    login(&mut user, "user").await;
+    send_message(&mut user, approve(vec![alternative(1)])).await;
+    frame_message_type!(user, OutgoingMessage::Vote);
+    frame_message_type!(admin, OutgoingMessage::Vote);
+
+    let message = IncomingMessage::FinishIssue(IncomingIssueStateChange { issue_id });
+    send_message(&mut admin, message).await;
+    frame_message_type!(admin, OutgoingMessage::Issue);
+    let result = frame_message_type!(admin, OutgoingMessage::Result);
+    let approvals: Vec<i64> = result.alternatives.iter().map(|a| a.votes).collect();
+    assert_eq!(approvals, vec![1, 2, 0]);
+    assert_eq!(result.vote_count, 2);
+    assert_eq!(result.winners, vec![alternative(1)]);
+    assert_eq!(result.outcome, Outcome::Passed);
+}
+
+#[actix_rt::test]
+async fn test_delegation() {
+    setup_once();
+    // Setup test server
+    let test_db = IntegrationTestDb::new().await;
+    let pool = test_db.pool();
+    let principal_id = user_id(&pool, "user").await;
+    let proxy_id = user_id(&pool, "admin").await;
+    sqlx::query("UPDATE issues SET allow_vote_change = true")
+        .execute(&pool)
+        .await
+        .unwrap();
+    let db_pool = pool.clone();
+    let mut srv = test::start(move || {
+        server::register_db_actor(pool.clone());
+        server::register_system_actors();
+        App::new().configure(server::configure)
+    });
+    let mut principal = srv.ws_at("/ws/").await.unwrap();
+    let issue = frame_message_type!(principal, OutgoingMessage::Issue);
+    let issue_id = issue.id.clone().unwrap();
+    login(&mut principal, "user").await;
+    let mut proxy = srv.ws_at("/ws/").await.unwrap();
+    frame_message_type!(proxy, OutgoingMessage::Issue);
+    login(&mut proxy, "admin").await;
+    let vote_for_principal = || {
+        IncomingMessage::Vote(IncomingVote {
+            user_id: None,
+            issue_id: issue_id.clone(),
+            alternative_id: issue.alternatives[0].id.clone(),
+            alternative_ids: Vec::new(),
+            on_behalf_of: Some(principal_id.clone()),
+        })
+    };
+    let grant = |proxy_id: &UserId| {
+        IncomingMessage::GrantDelegation(IncomingGrantDelegation {
+            proxy_id: proxy_id.clone(),
+            issue_id: None,
+            meeting_id: None,
+        })
+    };
+
+    // Voting for someone else requires a delegation
+    send_message(&mut proxy, vote_for_principal()).await;
+    let error = frame_message_type!(proxy, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::NotDelegated);
+
+    send_request(&mut principal, RequestId::Number(1), grant(&proxy_id)).await;
+    frame_message_type!(principal, OutgoingMessage::Ack);
+    send_message(&mut principal, grant(&proxy_id)).await;
+    let error = frame_message_type!(principal, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::AlreadyDelegated);
+    send_message(&mut principal, grant(&principal_id)).await;
+    let error = frame_message_type!(principal, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::InvalidDelegation);
+
+    // The vote belongs to the principal and records the proxy
+    send_message(&mut proxy, vote_for_principal()).await;
+    let vote = frame_message_type!(proxy, OutgoingMessage::Vote);
+    assert_eq!(vote.user_id.as_ref(), Some(&principal_id));
+    assert_eq!(vote.cast_by.as_ref(), Some(&proxy_id));
+    let vote = frame_message_type!(principal, OutgoingMessage::Vote);
+    assert_eq!(vote.cast_by.as_ref(), Some(&proxy_id));
+    let (cast_by,): (Option<Uuid>,) =
+        sqlx::query_as("SELECT cast_by FROM votes WHERE user_id = $1")
+            .bind(principal_id.0)
+            .fetch_one(&db_pool)
+            .await
+            .unwrap();
+    assert_eq!(cast_by, Some(proxy_id.0));
+
+    // Proxies retract the principal's vote under the same delegation
+    let retract_for_principal = || {
+        IncomingMessage::RetractVote(IncomingRetractVote {
+            issue_id: issue_id.clone(),
+            on_behalf_of: Some(principal_id.clone()),
+        })
+    };
+    send_message(&mut proxy, retract_for_principal()).await;
+    let vote = frame_message_type!(proxy, OutgoingMessage::VoteRetracted);
+    assert_eq!(vote.user_id.as_ref(), Some(&principal_id));
+    frame_message_type!(principal, OutgoingMessage::VoteRetracted);
+    send_message(&mut proxy, vote_for_principal()).await;
+    frame_message_type!(proxy, OutgoingMessage::Vote);
+    frame_message_type!(principal, OutgoingMessage::Vote);
+
+    let revoke = || {
+        IncomingMessage::RevokeDelegation(IncomingRevokeDelegation {
+            issue_id: None,
+            meeting_id: None,
+        })
+    };
+    send_request(&mut principal, RequestId::Number(2), revoke()).await;
+    frame_message_type!(principal, OutgoingMessage::Ack);
+    send_message(&mut principal, revoke()).await;
+    let error = frame_message_type!(principal, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::DelegationNotFound);
+
+    send_message(&mut proxy, retract_for_principal()).await;
+    let error = frame_message_type!(proxy, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::NotDelegated);
+}
+
+#[actix_rt::test]
+async fn test_fake_votes_rejected() {
+    setup_once();
+    // Setup test server
+    let test_db = IntegrationTestDb::new().await;
+    let pool = test_db.pool();
+    let user_id = user_id(&pool, "user").await;
+    let mut srv = test::start(move || {
+        server::register_db_actor(pool.clone());
+        server::register_system_actors();
+        App::new().configure(server::configure)
+    });
+    let mut framed = srv.ws_at("/ws/").await.unwrap();
+    let issue = frame_message_type!(framed, OutgoingMessage::Issue);
+    let fake_vote = || {
+        IncomingMessage::Vote(IncomingVote {
+            user_id: Some(user_id.clone()),
+            issue_id: issue.id.clone().unwrap(),
+            alternative_id: issue.alternatives[0].id.clone(),
+            alternative_ids: Vec::new(),
+            on_behalf_of: None,
+        })
+    };
+
+    // Anonymous sockets can't vote for anybody
+    send_message(&mut framed, fake_vote()).await;
+    let error = frame_message_type!(framed, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::NotLoggedIn);
+
+    // Not even admins without demo mode
+    login(&mut framed, "admin").await;
+    send_message(&mut framed, fake_vote()).await;
+    let error = frame_message_type!(framed, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::DemoModeDisabled);
+}
+
+#[actix_rt::test]
+async fn test_demo_votes() {
+    setup_once();
+    // Setup test server
+    let test_db = IntegrationTestDb::new().await;
+    let pool = test_db.pool();
+    let (observer_id, user_id) = (
+        user_id(&pool, "observer").await,
+        user_id(&pool, "user").await,
+    );
+    let db_pool = pool.clone();
+    let mut srv = test::start(move || {
+        server::register_db_actor(pool.clone());
+        server::register_system_actors();
+        App::new()
+            .app_data(server::Settings { demo_mode: true })
+            .configure(server::configure)
+    });
+    let mut framed = srv.ws_at("/ws/").await.unwrap();
+    let issue = frame_message_type!(framed, OutgoingMessage::Issue);
+    let fake_vote = |user_id: &UserId| {
+        IncomingMessage::Vote(IncomingVote {
+            user_id: Some(user_id.clone()),
+            issue_id: issue.id.clone().unwrap(),
+            alternative_id: issue.alternatives[0].id.clone(),
+            alternative_ids: Vec::new(),
+            on_behalf_of: None,
+        })
+    };
+
+    send_message(&mut framed, fake_vote(&user_id)).await;
+    let error = frame_message_type!(framed, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::NotLoggedIn);
+
+    // Only admins may vote for others in demo mode
+    let mut voter = srv.ws_at("/ws/").await.unwrap();
+    frame_message_type!(voter, OutgoingMessage::Issue);
+    login(&mut voter, "user").await;
+    send_message(&mut voter, fake_vote(&observer_id)).await;
+    let error = frame_message_type!(voter, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::PermissionDenied);
+
+    // Admins only vote for users who may vote themselves
+    login(&mut framed, "admin").await;
+    send_message(&mut framed, fake_vote(&observer_id)).await;
+    let error = frame_message_type!(framed, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::NotEligible);
+    send_message(&mut framed, fake_vote(&UserId::new())).await;
+    let error = frame_message_type!(framed, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::NotEligible);
+    send_request(&mut framed, RequestId::Number(1), fake_vote(&user_id)).await;
+    let vote = frame_message_type!(framed, OutgoingMessage::Vote);
+    assert_eq!(vote.user_id, Some(user_id.clone()));
+    frame_message_type!(framed, OutgoingMessage::Ack);
+
+    // Every demo vote is kept in the audit log
+    let entries: Vec<(String, Uuid, String)> = sqlx::query_as(
+        "SELECT users.username::text, audit_log.user_id, action FROM audit_log
+        JOIN users ON users.id = audit_log.actor_id",
+    )
+    .fetch_all(&db_pool)
+    .await
+    .unwrap();
+    assert_eq!(
+        entries,
+        vec![("admin".to_owned(), user_id.0, "demo_vote".to_owned())]
+    );
+}
+
+#[actix_rt::test]
+async fn test_weighted_votes() {
+    setup_once();
+    // Setup test server
+    let test_db = IntegrationTestDb::new().await;
+    let pool = test_db.pool();
+    sqlx::query("UPDATE issues SET max_weight = 5")
+        .execute(&pool)
+        .await
+        .unwrap();
+    let voter_id = user_id(&pool, "user").await;
+    let admin_id = user_id(&pool, "admin").await;
+    let mut srv = test::start(move || {
+        server::register_db_actor(pool.clone());
+        server::register_system_actors();
+        App::new().configure(server::configure)
+    });
+    let mut admin = srv.ws_at("/ws/").await.unwrap();
+    let issue = frame_message_type!(admin, OutgoingMessage::Issue);
+    assert_eq!(issue.max_weight, Some(5));
+    login(&mut admin, "admin").await;
+    let mut voter = srv.ws_at("/ws/").await.unwrap();
+    frame_message_type!(voter, OutgoingMessage::Issue);
+    login(&mut voter, "user").await;
+    let set_weight = |user_id: &UserId, weight: i32| {
+        IncomingMessage::SetVoteWeight(IncomingSetVoteWeight {
+            user_id: user_id.clone(),
+            weight,
+        })
+    };
+    let vote = |alternative: usize| {
+        IncomingMessage::Vote(IncomingVote {
+            user_id: None,
+            issue_id: issue.id.clone().unwrap(),
+            alternative_id: issue.alternatives[alternative].id.clone(),
+            alternative_ids: Vec::new(),
+            on_behalf_of: None,
+        })
+    };
+
+    // Only admins set weights, and only positive ones
+    send_message(&mut voter, set_weight(&voter_id, 10)).await;
+    let error = frame_message_type!(voter, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::PermissionDenied);
+    send_message(&mut admin, set_weight(&voter_id, 0)).await;
+    let error = frame_message_type!(admin, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::InvalidWeight);
+    send_request(&mut admin, RequestId::Number(1), set_weight(&voter_id, 3)).await;
+    frame_message_type!(admin, OutgoingMessage::Ack);
+
+    send_message(&mut voter, vote(0)).await;
+    let own_vote = frame_message_type!(voter, OutgoingMessage::Vote);
+    assert_eq!(own_vote.weight, Some(3));
+    frame_message_type!(admin, OutgoingMessage::Vote);
+
+    // The total weight can't exceed the max weight of the issue
+    send_request(&mut admin, RequestId::Number(2), set_weight(&admin_id, 3)).await;
+    frame_message_type!(admin, OutgoingMessage::Ack);
+    send_message(&mut admin, vote(1)).await;
+    let error = frame_message_type!(admin, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::MaxWeightReached);
+
+    // Reaching the max weight finishes the issue
+    send_request(&mut admin, RequestId::Number(3), set_weight(&admin_id, 2)).await;
+    frame_message_type!(admin, OutgoingMessage::Ack);
+    send_message(&mut admin, vote(1)).await;
+    frame_message_type!(admin, OutgoingMessage::Vote);
+    let finished = frame_message_type!(admin, OutgoingMessage::Issue);
+    assert_eq!(finished.state, Some(IssueState::Finished));
+    let result = frame_message_type!(admin, OutgoingMessage::Result);
+    let counts: Vec<(i64, i64)> = result
+        .alternatives
+        .iter()
+        .map(|alternative| (alternative.votes, alternative.weight))
+        .collect();
+    assert_eq!(counts, vec![(1, 3), (1, 2), (0, 0)]);
+    assert_eq!(
+        result.winners,
+        vec![issue.alternatives[0].id.clone().unwrap()]
+    );
+    assert_eq!(result.total_weight, 5);
+    assert_eq!(result.max_weight, Some(5));
+}
+
+#[actix_rt::test]
+async fn test_meetings() {
+    setup_once();
+    // Setup test server
+    let test_db = IntegrationTestDb::new().await;
+    let pool = test_db.pool();
+    let voter_id = user_id(&pool, "user").await;
+    let mut srv = test::start(move || {
+        server::register_db_actor(pool.clone());
+        server::register_system_actors();
+        App::new().configure(server::configure)
+    });
+    let mut admin = srv.ws_at("/ws/").await.unwrap();
+    let fixture_issue = frame_message_type!(admin, OutgoingMessage::Issue);
+    login(&mut admin, "admin").await;
+    let mut voter = srv.ws_at("/ws/").await.unwrap();
+    frame_message_type!(voter, OutgoingMessage::Issue);
+    login(&mut voter, "user").await;
+    let create_meeting = || {
+        IncomingMessage::CreateMeeting(IncomingCreateMeeting {
+            title: "general assembly".to_owned(),
+        })
+    };
+    let join = |meeting_id: &MeetingId| {
+        IncomingMessage::JoinMeeting(IncomingJoinMeeting {
+            meeting_id: meeting_id.clone(),
+        })
+    };
+
+    send_message(&mut voter, create_meeting()).await;
+    let error = frame_message_type!(voter, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::PermissionDenied);
+    send_message(&mut admin, create_meeting()).await;
+    let meeting = frame_message_type!(admin, OutgoingMessage::Meeting);
+    assert_eq!(meeting.title, "general assembly");
+    assert!(meeting.issue.is_none());
+
+    send_message(&mut voter, join(&MeetingId::new())).await;
+    let error = frame_message_type!(voter, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::MeetingNotFound);
+
+    // Issues can only be added to existing meetings
+    let mut new_issue = copy_issue(&fixture_issue);
+    new_issue.title = "meeting issue".to_owned();
+    new_issue.meeting_id = Some(MeetingId::new());
+    let create_issue = |issue: &IncomingIssue| {
+        IncomingMessage::CreateIssue(IncomingCreateIssue {
+            issue: issue.clone(),
+        })
+    };
+    send_message(&mut admin, create_issue(&new_issue)).await;
+    let error = frame_message_type!(admin, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::MeetingNotFound);
+
+    // Broadcasts about the meeting's issues only reach clients in the meeting
+    send_message(&mut admin, join(&meeting.id)).await;
+    let joined = frame_message_type!(admin, OutgoingMessage::Meeting);
+    assert!(joined.issue.is_none());
+    new_issue.meeting_id = Some(meeting.id.clone());
+    send_message(&mut admin, create_issue(&new_issue)).await;
+    let issue = frame_message_type!(admin, OutgoingMessage::IssueCreated).issue;
+    assert_eq!(issue.meeting_id.as_ref(), Some(&meeting.id));
+    let issue_id = issue.id.clone().unwrap();
+    assert!(read_messages(&mut admin).await.is_empty());
+    assert!(read_messages(&mut voter).await.is_empty());
+
+    // Besides admins and chairs only the voters on the roll join the meeting
+    let mut anonymous = srv.ws_at("/ws/").await.unwrap();
+    frame_message_type!(anonymous, OutgoingMessage::Issue);
+    send_message(&mut anonymous, join(&meeting.id)).await;
+    let error = frame_message_type!(anonymous, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::NotLoggedIn);
+    send_message(&mut voter, join(&meeting.id)).await;
+    let error = frame_message_type!(voter, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::PermissionDenied);
+    let message = IncomingMessage::AddMeetingVoter(IncomingMeetingVoter {
+        meeting_id: meeting.id.clone(),
+        user_id: voter_id.clone(),
+    });
+    send_request(&mut admin, RequestId::Number(1), message).await;
+    frame_message_type!(admin, OutgoingMessage::Ack);
+
+    // Joining shows the meeting's active issue
+    send_request(&mut voter, RequestId::Number(1), join(&meeting.id)).await;
+    let joined = frame_message_type!(voter, OutgoingMessage::Meeting);
+    assert_eq!(joined.issue.unwrap().id, Some(issue_id.clone()));
+    frame_message_type!(voter, OutgoingMessage::Ack);
+    let message = IncomingMessage::StartIssue(IncomingIssueStateChange {
+        issue_id: issue_id.clone(),
+    });
+    send_message(&mut admin, message).await;
+    frame_message_type!(admin, OutgoingMessage::Issue);
+    let started = frame_message_type!(voter, OutgoingMessage::Issue);
+    assert_eq!(started.state, Some(IssueState::InProgress));
+
+    // The meeting has its own active issue
+    let message = IncomingMessage::ActivateIssue(IncomingActivateIssue {
+        issue_id: issue_id.clone(),
+    });
+    send_message(&mut admin, message).await;
+    frame_message_type!(admin, OutgoingMessage::Issue);
+    frame_message_type!(voter, OutgoingMessage::Issue);
+    let mut lobby = srv.ws_at("/ws/").await.unwrap();
+    let lobby_issue = frame_message_type!(lobby, OutgoingMessage::Issue);
+    assert_eq!(lobby_issue.id, fixture_issue.id);
+    assert!(read_messages(&mut lobby).await.is_empty());
+
+    // The creator gets the issue even if it hasn't joined the issue's meeting
+    login(&mut lobby, "admin").await;
+    let request_id = RequestId::String("create".to_owned());
+    send_request(&mut lobby, request_id.clone(), create_issue(&new_issue)).await;
+    let created = frame_message_type!(lobby, OutgoingMessage::IssueCreated);
+    assert_eq!(created.request_id, Some(request_id));
+    assert_eq!(created.issue.meeting_id.as_ref(), Some(&meeting.id));
+    let broadcast = frame_message_type!(admin, OutgoingMessage::IssueCreated);
+    assert_eq!(broadcast.issue.id, created.issue.id);
+    assert_eq!(broadcast.request_id, None);
+}
+
+#[actix_rt::test]
+async fn test_leave_meeting() {
+    setup_once();
+    // Setup test server
+    let test_db = IntegrationTestDb::new().await;
+    let pool = test_db.pool();
+    let voter_id = user_id(&pool, "user").await;
+    let mut srv = test::start(move || {
+        server::register_db_actor(pool.clone());
+        server::register_system_actors();
+        App::new().configure(server::configure)
+    });
+    let mut admin = srv.ws_at("/ws/").await.unwrap();
+    let fixture_issue = frame_message_type!(admin, OutgoingMessage::Issue);
+    login(&mut admin, "admin").await;
+    let message = IncomingMessage::CreateMeeting(IncomingCreateMeeting {
+        title: "general assembly".to_owned(),
+    });
+    send_message(&mut admin, message).await;
+    let meeting = frame_message_type!(admin, OutgoingMessage::Meeting);
+    let voter = |request_id: u64, add: bool| {
+        let voter = IncomingMeetingVoter {
+            meeting_id: meeting.id.clone(),
+            user_id: voter_id.clone(),
+        };
+        let message = if add {
+            IncomingMessage::AddMeetingVoter(voter)
+        } else {
+            IncomingMessage::RemoveMeetingVoter(voter)
+        };
+        (RequestId::Number(request_id), message)
+    };
+    let (request_id, message) = voter(1, true);
+    send_request(&mut admin, request_id, message).await;
+    frame_message_type!(admin, OutgoingMessage::Ack);
+    let join = || {
+        IncomingMessage::JoinMeeting(IncomingJoinMeeting {
+            meeting_id: meeting.id.clone(),
+        })
+    };
+    send_message(&mut admin, join()).await;
+    frame_message_type!(admin, OutgoingMessage::Meeting);
+
+    // The voter attends the meeting with two clients
+    let mut first = srv.ws_at("/ws/").await.unwrap();
+    let mut second = srv.ws_at("/ws/").await.unwrap();
+    for client in [&mut first, &mut second] {
+        frame_message_type!(*client, OutgoingMessage::Issue);
+        login(client, "user").await;
+        send_message(client, join()).await;
+        frame_message_type!(*client, OutgoingMessage::Meeting);
+    }
+    let mut new_issue = copy_issue(&fixture_issue);
+    new_issue.meeting_id = Some(meeting.id.clone());
+    let create_issue = || {
+        IncomingMessage::CreateIssue(IncomingCreateIssue {
+            issue: new_issue.clone(),
+        })
+    };
+
+    // Logging out moves the client back to the lobby
+    send_request(&mut first, RequestId::Number(1), IncomingMessage::Logout).await;
+    frame_message_type!(first, OutgoingMessage::Ack);
+    send_message(&mut admin, create_issue()).await;
+    frame_message_type!(admin, OutgoingMessage::IssueCreated);
+    frame_message_type!(second, OutgoingMessage::IssueCreated);
+    assert!(read_messages(&mut first).await.is_empty());
+
+    // So does removing the voter from the roll
+    let (request_id, message) = voter(2, false);
+    send_request(&mut admin, request_id, message).await;
+    frame_message_type!(admin, OutgoingMessage::Ack);
+    let left = read_message(&mut second).await;
+    assert!(matches!(left, Some(OutgoingMessage::MeetingLeft)));
+    send_message(&mut admin, create_issue()).await;
+    frame_message_type!(admin, OutgoingMessage::IssueCreated);
+    assert!(read_messages(&mut second).await.is_empty());
+    assert!(read_messages(&mut first).await.is_empty());
+}
+
+#[actix_rt::test]
+async fn test_voter_roll() {
+    setup_once();
+    // Setup test server
+    let test_db = IntegrationTestDb::new().await;
+    let pool = test_db.pool();
+    let voter_id = user_id(&pool, "user").await;
+    let admin_id = user_id(&pool, "admin").await;
+    let mut srv = test::start(move || {
+        server::register_db_actor(pool.clone());
+        server::register_system_actors();
+        App::new().configure(server::configure)
+    });
+    let mut admin = srv.ws_at("/ws/").await.unwrap();
+    let fixture_issue = frame_message_type!(admin, OutgoingMessage::Issue);
+    login(&mut admin, "admin").await;
+    let mut voter = srv.ws_at("/ws/").await.unwrap();
+    frame_message_type!(voter, OutgoingMessage::Issue);
+    login(&mut voter, "user").await;
+
+    let message = IncomingMessage::CreateMeeting(IncomingCreateMeeting {
+        title: "shareholder meeting".to_owned(),
+    });
+    send_message(&mut admin, message).await;
+    let meeting = frame_message_type!(admin, OutgoingMessage::Meeting);
+    let join = || {
+        IncomingMessage::JoinMeeting(IncomingJoinMeeting {
+            meeting_id: meeting.id.clone(),
+        })
+    };
+    send_message(&mut admin, join()).await;
+    frame_message_type!(admin, OutgoingMessage::Meeting);
+    let add_voter = |user_id: &UserId| {
+        IncomingMessage::AddMeetingVoter(IncomingMeetingVoter {
+            meeting_id: meeting.id.clone(),
+            user_id: user_id.clone(),
+        })
+    };
+    let mut new_issue = copy_issue(&fixture_issue);
+    new_issue.max_voters = None;
+    new_issue.meeting_id = Some(meeting.id.clone());
+    let create_issue = || {
+        IncomingMessage::CreateIssue(IncomingCreateIssue {
+            issue: new_issue.clone(),
+        })
+    };
+
+    // Max voters is taken from the voter roll, which must not be empty
+    send_message(&mut admin, create_issue()).await;
+    let error = frame_message_type!(admin, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::NoEligibleVoters);
+
+    // Only admins manage the voter roll
+    send_message(&mut voter, add_voter(&voter_id)).await;
+    let error = frame_message_type!(voter, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::PermissionDenied);
+    send_message(&mut admin, add_voter(&UserId::new())).await;
+    let error = frame_message_type!(admin, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::UserNotFound);
+    send_request(&mut admin, RequestId::Number(1), add_voter(&voter_id)).await;
+    frame_message_type!(admin, OutgoingMessage::Ack);
+    send_request(&mut admin, RequestId::Number(2), add_voter(&voter_id)).await;
+    frame_message_type!(admin, OutgoingMessage::Ack);
+
+    send_message(&mut admin, create_issue()).await;
+    let issue = frame_message_type!(admin, OutgoingMessage::IssueCreated).issue;
+    assert_eq!(issue.max_voters, Some(1));
+    let message = IncomingMessage::StartIssue(IncomingIssueStateChange {
+        issue_id: issue.id.clone().unwrap(),
+    });
+    send_message(&mut admin, message).await;
+    frame_message_type!(admin, OutgoingMessage::Issue);
+    let vote = || {
+        IncomingMessage::Vote(IncomingVote {
+            user_id: None,
+            issue_id: issue.id.clone().unwrap(),
+            alternative_id: issue.alternatives[0].id.clone(),
+            alternative_ids: Vec::new(),
+            on_behalf_of: None,
+        })
+    };
+
+    // Users who aren't on the roll can't vote, whatever their role
+    send_message(&mut admin, vote()).await;
+    let error = frame_message_type!(admin, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::NotEligible);
+
+    // Voters added after the issue was created may vote and raise max voters
+    send_request(&mut admin, RequestId::Number(3), add_voter(&admin_id)).await;
+    frame_message_type!(admin, OutgoingMessage::Ack);
+    send_message(&mut admin, vote()).await;
+    frame_message_type!(admin, OutgoingMessage::Vote);
+
+    send_message(&mut voter, join()).await;
+    let joined = frame_message_type!(voter, OutgoingMessage::Meeting);
+    assert_eq!(joined.issue.unwrap().max_voters, Some(2));
+    send_message(&mut voter, vote()).await;
+    frame_message_type!(voter, OutgoingMessage::Vote);
+    let finished = frame_message_type!(voter, OutgoingMessage::Issue);
+    assert_eq!(finished.state, Some(IssueState::Finished));
+
+    let message = IncomingMessage::RemoveMeetingVoter(IncomingMeetingVoter {
+        meeting_id: meeting.id.clone(),
+        user_id: voter_id.clone(),
+    });
+    send_request(&mut admin, RequestId::Number(4), message).await;
+    let messages = read_messages(&mut admin).await;
+    assert!(matches!(messages.last(), Some(OutgoingMessage::Ack(_))));
+}
+
+#[actix_rt::test]
+async fn test_meeting_delegation() {
+    setup_once();
+    // Setup test server
+    let test_db = IntegrationTestDb::new().await;
+    let pool = test_db.pool();
+    let principal_id = user_id(&pool, "user").await;
+    let proxy_id = user_id(&pool, "admin").await;
+    let mut srv = test::start(move || {
+        server::register_db_actor(pool.clone());
+        server::register_system_actors();
+        App::new().configure(server::configure)
+    });
+    let mut proxy = srv.ws_at("/ws/").await.unwrap();
+    let fixture_issue = frame_message_type!(proxy, OutgoingMessage::Issue);
+    login(&mut proxy, "admin").await;
+    let mut principal = srv.ws_at("/ws/").await.unwrap();
+    frame_message_type!(principal, OutgoingMessage::Issue);
+    login(&mut principal, "user").await;
+
+    // Only the principal is on the voter roll of the meeting
+    let message = IncomingMessage::CreateMeeting(IncomingCreateMeeting {
+        title: "shareholder meeting".to_owned(),
+    });
+    send_message(&mut proxy, message).await;
+    let meeting = frame_message_type!(proxy, OutgoingMessage::Meeting);
+    let message = IncomingMessage::AddMeetingVoter(IncomingMeetingVoter {
+        meeting_id: meeting.id.clone(),
+        user_id: principal_id.clone(),
+    });
+    send_request(&mut proxy, RequestId::Number(1), message).await;
+    frame_message_type!(proxy, OutgoingMessage::Ack);
+    let message = IncomingMessage::JoinMeeting(IncomingJoinMeeting {
+        meeting_id: meeting.id.clone(),
+    });
+    send_message(&mut proxy, message).await;
+    frame_message_type!(proxy, OutgoingMessage::Meeting);
+    let mut new_issue = copy_issue(&fixture_issue);
+    new_issue.max_voters = None;
+    new_issue.meeting_id = Some(meeting.id.clone());
+    let message = IncomingMessage::CreateIssue(IncomingCreateIssue { issue: new_issue });
+    send_message(&mut proxy, message).await;
+    let issue = frame_message_type!(proxy, OutgoingMessage::IssueCreated).issue;
+    let issue_id = issue.id.unwrap();
+    let message = IncomingMessage::StartIssue(IncomingIssueStateChange {
+        issue_id: issue_id.clone(),
+    });
+    send_message(&mut proxy, message).await;
+    frame_message_type!(proxy, OutgoingMessage::Issue);
+    let abstain = |issue_id: &IssueId, on_behalf_of: Option<&UserId>| {
+        IncomingMessage::Abstain(IncomingAbstain {
+            issue_id: issue_id.clone(),
+            on_behalf_of: on_behalf_of.cloned(),
+        })
+    };
+
+    // A delegation is limited to either an issue or a meeting
+    let grant = |issue_id: Option<IssueId>| {
+        IncomingMessage::GrantDelegation(IncomingGrantDelegation {
+            proxy_id: proxy_id.clone(),
+            issue_id,
+            meeting_id: Some(meeting.id.clone()),
+        })
+    };
+    send_message(&mut principal, grant(Some(issue_id.clone()))).await;
+    let error = frame_message_type!(principal, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::InvalidDelegation);
+    send_request(&mut principal, RequestId::Number(1), grant(None)).await;
+    frame_message_type!(principal, OutgoingMessage::Ack);
+
+    // The delegation doesn't apply to issues outside of the meeting
+    let fixture_issue_id = fixture_issue.id.unwrap();
+    send_message(&mut proxy, abstain(&fixture_issue_id, Some(&principal_id))).await;
+    let error = frame_message_type!(proxy, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::NotDelegated);
+
+    // The proxy may vote for the principal on the roll, but not for themselves
+    send_message(&mut proxy, abstain(&issue_id, None)).await;
+    let error = frame_message_type!(proxy, OutgoingMessage::Error);
+    assert_eq!(error.code, ErrorCode::NotEligible);
+    send_message(&mut proxy, abstain(&issue_id, Some(&principal_id))).await;
+    let vote = frame_message_type!(proxy, OutgoingMessage::Vote);
+    assert_eq!(vote.user_id.as_ref(), Some(&principal_id));
+    assert_eq!(vote.cast_by.as_ref(), Some(&proxy_id));
+}